@@ -0,0 +1,186 @@
+use crate::domain::error::ErrorResponse;
+use crate::domain::error::ErrorResponse::{InvalidUploadError, PayloadTooLargeError, UnauthorizedError};
+use crate::domain::image_path::normalize;
+use crate::domain::purge::{PurgeScope, PurgeSummary};
+use crate::domain::upload::StoredImage;
+use crate::image_service::inspect_image;
+use crate::repository::mount::MountTable;
+use crate::{CONFIG, MOUNT_TABLE};
+use http_body_util::{BodyExt, LengthLimitError, Limited};
+use hyper::body::Incoming;
+use hyper::header::{AUTHORIZATION, CONTENT_LENGTH};
+use hyper::HeaderMap;
+use image::ImageFormat;
+use lazy_static::lazy_static;
+use tracing::{info, instrument, warn};
+
+const ADMIN_TOKEN_ENV: &str = "ADMIN_TOKEN";
+const BEARER_PREFIX: &str = "Bearer ";
+
+lazy_static! {
+    /// Admin endpoints are disabled entirely when no token is configured.
+    static ref ADMIN_TOKEN: Option<String> =
+        std::env::var(ADMIN_TOKEN_ENV).ok().filter(|token| !token.is_empty());
+}
+
+/// Check the request carries `Authorization: Bearer <ADMIN_TOKEN>`.
+pub fn authorize(headers: &HeaderMap) -> Result<(), ErrorResponse> {
+    authorize_token(ADMIN_TOKEN.as_deref(), headers)
+}
+
+fn authorize_token(expected: Option<&str>, headers: &HeaderMap) -> Result<(), ErrorResponse> {
+    let provided = headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix(BEARER_PREFIX));
+    match (expected, provided) {
+        (Some(expected), Some(provided)) if constant_time_eq(expected.as_bytes(), provided.as_bytes()) => Ok(()),
+        _ => {
+            warn!("Rejected unauthorized admin request");
+            Err(UnauthorizedError {})
+        }
+    }
+}
+
+/// Purge every layer of every mount of entries matching the query, see `PurgeScope::from_query`.
+#[instrument(skip(headers))]
+pub async fn process_purge(headers: &HeaderMap, opt_query: Option<&str>) -> Result<PurgeSummary, ErrorResponse> {
+    authorize(headers)?;
+    purge_mounts(&MOUNT_TABLE, opt_query).await
+}
+
+async fn purge_mounts(table: &MountTable, opt_query: Option<&str>) -> Result<PurgeSummary, ErrorResponse> {
+    let scope = PurgeScope::from_query(opt_query)?;
+    info!("Purging {scope:?}");
+
+    table.purge(&scope).await
+}
+
+/// Store the request body as the original at `path`, in the origin of its mount, and
+/// warm the caches in front of it. See `UploadConfig` for what is accepted.
+#[instrument(skip(headers, body))]
+pub async fn process_upload(headers: &HeaderMap, path: &str, body: Incoming) -> Result<StoredImage, ErrorResponse> {
+    authorize(headers)?;
+    // Resolved as normalized, so an upload cannot slip past the mount its path is under.
+    let path: &str = &normalize(path)?;
+    let policy = &CONFIG.upload;
+    let declared_length = headers
+        .get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok());
+    if declared_length.is_some_and(|length| length > policy.max_bytes) {
+        return Err(PayloadTooLargeError {});
+    }
+    let bytes = Limited::new(body, policy.max_bytes as usize)
+        .collect()
+        .await
+        .map_err(|e| {
+            if e.is::<LengthLimitError>() {
+                PayloadTooLargeError {}
+            } else {
+                InvalidUploadError {}
+            }
+        })?
+        .to_bytes();
+
+    let (format, width, height) = inspect_image(&bytes).map_err(|_| InvalidUploadError {})?;
+    if ImageFormat::from_path(path).ok() != Some(format)
+        || !policy.allows_format(format)
+        || !policy.allows_dimensions(width, height)
+    {
+        warn!("Rejected upload of a {width}x{height} {format:?} image to {path}");
+        return Err(InvalidUploadError {});
+    }
+
+    let (mount, mount_path) = MOUNT_TABLE.resolve(path);
+    let content_type = format.to_mime_type();
+    let size = bytes.len() as u64;
+    let (origin, metadata) = mount.chain.store_image(&mount_path, bytes, content_type).await?;
+    info!("Stored {size} bytes at {path} in {origin}");
+    Ok(StoredImage {
+        path: path.to_string(),
+        origin,
+        content_type: content_type.to_string(),
+        width,
+        height,
+        bytes: size,
+        etag: metadata.etag,
+        generation: metadata.generation,
+    })
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::CacheConfig;
+    use crate::domain::error::ErrorResponse::InvalidPurgeRequestError;
+    use crate::domain::image_item::{ImageItem, OriginMetadata};
+    use crate::domain::transform_policy::TransformPolicy;
+    use crate::repository::chain::RepositoryChain;
+    use crate::repository::memory_repository::MemoryRepository;
+    use crate::repository::mount::Mount;
+    use crate::repository::ImageRepository;
+    use hyper::body::Bytes;
+    use hyper::StatusCode;
+    use std::sync::Arc;
+
+    #[test]
+    fn constant_time_eq_compares_bytes() {
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secret", b"secreT"));
+        assert!(!constant_time_eq(b"secret", b"secrets"))
+    }
+
+    fn bearer(token: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(AUTHORIZATION, format!("{BEARER_PREFIX}{token}").parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn authorize_needs_the_configured_token() {
+        assert!(authorize_token(Some("secret"), &bearer("secret")).is_ok());
+        for (expected, headers) in [
+            (Some("secret"), HeaderMap::new()),
+            (Some("secret"), bearer("guess")),
+            (None, bearer("secret")),
+            (None, bearer("")),
+        ] {
+            let error = authorize_token(expected, &headers).unwrap_err();
+            assert_eq!(error.handle().unwrap().status(), StatusCode::UNAUTHORIZED);
+        }
+    }
+
+    #[tokio::test]
+    async fn purge_scopes_are_routed_to_mounts() {
+        let cache = CacheConfig::default();
+        let default_memory = Arc::new(MemoryRepository::new(1024));
+        let shop_memory = Arc::new(MemoryRepository::new(1024));
+        let item = ImageItem {
+            bytes: Bytes::from_static(b"image"),
+            metadata: OriginMetadata::new(None, None, None),
+        };
+        default_memory.write_image("/a.jpg", &item).await.unwrap();
+        shop_memory.write_image("/a.jpg", &item).await.unwrap();
+        let shop = Mount {
+            prefix: "/shop/".to_string(),
+            origin: None,
+            chain: Arc::new(RepositoryChain::new(vec![shop_memory.clone()], &cache)),
+            transform: TransformPolicy::default(),
+        };
+        let table = MountTable::new(vec![shop], Arc::new(RepositoryChain::new(vec![default_memory.clone()], &cache)));
+
+        let summary = purge_mounts(&table, Some("path=/shop/a.jpg")).await.unwrap();
+        assert_eq!(summary.entries, 1);
+        assert!(shop_memory.read_image("/a.jpg").await.is_err());
+        assert!(default_memory.read_image("/a.jpg").await.is_ok());
+
+        assert!(matches!(purge_mounts(&table, Some("path=/a.jpg&all=true")).await, Err(InvalidPurgeRequestError {})));
+        assert_eq!(purge_mounts(&table, Some("all=true")).await.unwrap().entries, 1);
+        assert!(default_memory.read_image("/a.jpg").await.is_err())
+    }
+}
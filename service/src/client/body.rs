@@ -0,0 +1,109 @@
+use crate::client::ClientError;
+use hyper::body::Bytes;
+use memmap2::Mmap;
+use reqwest::Response;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use tokio::io::AsyncWriteExt;
+use tracing::debug;
+
+/// How much of an origin response body is read, and where.
+#[derive(Debug, Clone)]
+pub struct BodyLimits {
+    /// Larger bodies are refused, up front when the origin sends a `Content-Length`.
+    pub max_bytes: u64,
+    /// Larger bodies are written to a file under `spool_dir` instead of memory.
+    pub spool_above: u64,
+    pub spool_dir: PathBuf,
+}
+
+/// Stream a response body within `limits`. Spooled bodies are mapped from an unlinked file,
+/// so their pages are backed by the disk rather than by the instance's memory and the file
+/// disappears with the last copy of the returned bytes.
+pub(crate) async fn read_body(mut resp: Response, limits: &BodyLimits) -> Result<Bytes, ClientError> {
+    if resp.content_length().is_some_and(|length| length > limits.max_bytes) {
+        return Err(ClientError::TooLarge(limits.max_bytes));
+    }
+    let mut buffer: Vec<u8> = Vec::new();
+    let mut spool: Option<tokio::fs::File> = None;
+    let mut length = 0u64;
+    while let Some(chunk) = resp.chunk().await? {
+        length += chunk.len() as u64;
+        if length > limits.max_bytes {
+            return Err(ClientError::TooLarge(limits.max_bytes));
+        }
+        match spool.as_mut() {
+            Some(file) => file.write_all(&chunk).await.map_err(ClientError::Spool)?,
+            None if length > limits.spool_above => {
+                debug!("Spooling response body to {}", limits.spool_dir.display());
+                let mut file = spool_file(&limits.spool_dir).await.map_err(ClientError::Spool)?;
+                file.write_all(&buffer).await.map_err(ClientError::Spool)?;
+                file.write_all(&chunk).await.map_err(ClientError::Spool)?;
+                buffer = Vec::new();
+                spool = Some(file);
+            }
+            None => buffer.extend_from_slice(&chunk),
+        }
+    }
+    match spool {
+        Some(mut file) => {
+            file.flush().await.map_err(ClientError::Spool)?;
+            map(file.into_std().await).map_err(ClientError::Spool)
+        }
+        None => Ok(Bytes::from(buffer)),
+    }
+}
+
+/// A new file in `dir`, unlinked straight away so it is never seen by anything else and is
+/// cleaned up by the OS even if the process dies.
+async fn spool_file(dir: &Path) -> std::io::Result<tokio::fs::File> {
+    tokio::fs::create_dir_all(dir).await?;
+    let path = dir.join(format!("spool-{:016x}.tmp", rand::random::<u64>()));
+    let file = tokio::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(&path)
+        .await?;
+    tokio::fs::remove_file(&path).await?;
+    Ok(file)
+}
+
+fn map(file: File) -> std::io::Result<Bytes> {
+    // SAFETY: the file was unlinked on creation and only this process holds it, nothing
+    // can truncate or modify it while mapped.
+    let mmap = unsafe { Mmap::map(&file)? };
+    Ok(Bytes::from_owner(mmap))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(body: &'static str) -> Response {
+        Response::from(hyper::Response::new(body))
+    }
+
+    fn limits(dir: &Path) -> BodyLimits {
+        BodyLimits {
+            max_bytes: 8,
+            spool_above: 4,
+            spool_dir: dir.to_path_buf(),
+        }
+    }
+
+    #[tokio::test]
+    async fn bodies_are_spooled_within_the_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        // Created on the first spooled body only.
+        let spool_dir = dir.path().join("spool");
+        assert_eq!(read_body(response("abc"), &limits(&spool_dir)).await.unwrap(), "abc");
+        assert!(!spool_dir.exists(), "small body was spooled");
+        assert_eq!(read_body(response("abcdefg"), &limits(&spool_dir)).await.unwrap(), "abcdefg");
+        assert!(spool_dir.exists(), "large body was not spooled");
+        assert_eq!(std::fs::read_dir(&spool_dir).unwrap().count(), 0, "spool file left behind");
+
+        let too_large = read_body(response("abcdefghi"), &limits(&spool_dir)).await;
+        assert!(matches!(too_large, Err(ClientError::TooLarge(8))))
+    }
+}
@@ -1,25 +1,190 @@
-use lazy_static::lazy_static;
-use tracing::info;
+use crate::client::body::BodyLimits;
+use crate::client::origin_guard::{OriginConfig, OriginGuard};
+use crate::client::{bucket_response, conditional, stored_response, BucketResponse, ClientError, ObjectClient};
+use crate::domain::image_item::OriginMetadata;
+use crate::domain::status::BreakerStatus;
+use async_trait::async_trait;
+use gcp_auth::TokenProvider;
+use hyper::body::Bytes;
+use reqwest::header::CONTENT_TYPE;
+use reqwest::{Method, RequestBuilder};
+use serde::Deserialize;
+use std::fmt::{Debug, Formatter};
+use std::sync::Arc;
+use tokio::sync::OnceCell;
+use tracing::{error, info};
 
-const BUCKET_URL: &str = "https://storage.googleapis.com/image-resizer_europe-west1";
+const GENERATION_HEADER: &str = "x-goog-generation";
+const READ_ONLY_SCOPE: &str = "https://www.googleapis.com/auth/devstorage.read_only";
+const READ_WRITE_SCOPE: &str = "https://www.googleapis.com/auth/devstorage.read_write";
 
-lazy_static! {
-    static ref BUCKET_CLIENT: reqwest::Client = bucket_client();
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct BucketConfig {
+    /// e.g. `http://localhost:4443` to run against fake-gcs-server.
+    pub endpoint: String,
+    pub bucket: String,
+    /// Send an OAuth token with each request, needed for private buckets.
+    pub authenticated: bool,
 }
 
+impl Default for BucketConfig {
+    fn default() -> Self {
+        BucketConfig {
+            endpoint: "https://storage.googleapis.com".to_string(),
+            bucket: "image-resizer_europe-west1".to_string(),
+            authenticated: false,
+        }
+    }
+}
+
+/// Client for a Google Cloud Storage bucket over the XML API. Authenticated clients get
+/// their tokens from a service account key in `GOOGLE_APPLICATION_CREDENTIALS`, the
+/// metadata server or gcloud, whichever is found first. The provider caches the token and
+/// fetches a new one shortly before it expires.
+pub struct BucketClient {
+    config: BucketConfig,
+    http: reqwest::Client,
+    guard: OriginGuard,
+    limits: BodyLimits,
+    token_provider: OnceCell<Arc<dyn TokenProvider>>,
+}
+
+impl Debug for BucketClient {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BucketClient").field("config", &self.config).finish()
+    }
+}
+
+impl BucketClient {
+    pub fn new(config: BucketConfig, origin: OriginConfig) -> Self {
+        info!("Initializing bucket client for {}.", config.bucket);
+        let http = reqwest::Client::builder()
+            .https_only(config.endpoint.starts_with("https://"))
+            .use_rustls_tls()
+            .connection_verbose(true)
+            .build()
+            .unwrap();
+        BucketClient {
+            config,
+            http,
+            limits: origin.body_limits(),
+            guard: OriginGuard::new(origin),
+            token_provider: OnceCell::new(),
+        }
+    }
+}
+
+#[async_trait]
+impl ObjectClient for BucketClient {
+    fn name(&self) -> &'static str {
+        "bucket"
+    }
+
+    async fn get(&self, path: &str) -> Result<BucketResponse, ClientError> {
+        self.guard
+            .call(|| async {
+                let resp = self.request(Method::GET, path, READ_ONLY_SCOPE).await?.send().await?;
+                bucket_response(resp, GENERATION_HEADER, &self.limits).await
+            })
+            .await
+    }
 
-pub async fn bucket_request(path: &str) -> Result<Vec<u8>, reqwest::Error> {
-    let url = String::from(BUCKET_URL) + path;
-    let resp = BUCKET_CLIENT.get(&url).send().await?;
-    resp.bytes().await.map(|d| d.to_vec())
+    async fn revalidate(&self, path: &str, metadata: &OriginMetadata) -> Result<BucketResponse, ClientError> {
+        self.guard
+            .call(|| async {
+                let request = self.request(Method::GET, path, READ_ONLY_SCOPE).await?;
+                let resp = conditional(request, metadata).send().await?;
+                bucket_response(resp, GENERATION_HEADER, &self.limits).await
+            })
+            .await
+    }
+
+    async fn put(&self, path: &str, body: Bytes, content_type: &str) -> Result<OriginMetadata, ClientError> {
+        self.guard
+            .call(|| async {
+                let resp = self
+                    .request(Method::PUT, path, READ_WRITE_SCOPE)
+                    .await?
+                    .header(CONTENT_TYPE, content_type)
+                    .body(body.clone())
+                    .send()
+                    .await?;
+                stored_response(resp, GENERATION_HEADER).await
+            })
+            .await
+    }
+
+    fn status(&self) -> BreakerStatus {
+        self.guard.status()
+    }
+}
+
+impl BucketClient {
+    async fn request(&self, method: Method, path: &str, scope: &str) -> Result<RequestBuilder, ClientError> {
+        let request = self.http.request(method, self.object_url(path));
+        if !self.config.authenticated {
+            return Ok(request);
+        }
+        let provider = self
+            .token_provider
+            .get_or_try_init(gcp_auth::provider)
+            .await
+            .inspect_err(|e| error!("Could not find GCP credentials: {e}"))?;
+        let token = provider.token(&[scope]).await?;
+        Ok(request.bearer_auth(token.as_str()))
+    }
+
+    fn object_url(&self, path: &str) -> String {
+        format!("{}/{}{path}", self.config.endpoint.trim_end_matches('/'), self.config.bucket)
+    }
 }
 
-fn bucket_client() -> reqwest::Client {
-    info!("Initializing bucket client.");
-    reqwest::Client::builder()
-        .https_only(true)
-        .use_rustls_tls()
-        .connection_verbose(true)
-        .build()
-        .unwrap()
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn client(endpoint: &str) -> BucketClient {
+        BucketClient::new(
+            BucketConfig {
+                endpoint: endpoint.to_string(),
+                bucket: "images".to_string(),
+                authenticated: false,
+            },
+            OriginConfig::default(),
+        )
+    }
+
+    #[test]
+    fn object_url_includes_bucket() {
+        assert_eq!(client("http://localhost:4443/").object_url("/a.jpg"), "http://localhost:4443/images/a.jpg")
+    }
+
+    /// Round trip against the fake-gcs-server service in docker-compose.yaml:
+    /// `docker compose up fake-gcs` then `cargo test -- --ignored`.
+    #[tokio::test]
+    #[ignore = "needs a local fake-gcs-server"]
+    async fn fake_gcs_round_trip() {
+        let client = client("http://localhost:4443");
+        let _ = client
+            .http
+            .post("http://localhost:4443/storage/v1/b")
+            .body(r#"{"name": "images"}"#)
+            .send()
+            .await;
+        let upload = client
+            .http
+            .post("http://localhost:4443/upload/storage/v1/b/images/o?uploadType=media&name=fixtures/pixel.bin")
+            .body("pixel")
+            .send()
+            .await
+            .unwrap();
+        assert!(upload.status().is_success(), "Upload failed with {}", upload.status());
+
+        match client.get("/fixtures/pixel.bin").await.unwrap() {
+            BucketResponse::Fetched(bytes, _) => assert_eq!(bytes, "pixel"),
+            _ => panic!("Expected the object back"),
+        }
+        assert!(matches!(client.get("/fixtures/missing.bin").await.unwrap(), BucketResponse::NotFound))
+    }
 }
@@ -0,0 +1,115 @@
+use crate::domain::status::{BreakerStatus, CircuitState};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Stops sending requests to an origin after `threshold` consecutive failures, for
+/// `open_for`, after which a single trial request decides whether it has recovered.
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    threshold: u32,
+    open_for: Duration,
+    state: Mutex<BreakerState>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum BreakerState {
+    Closed { failures: u32 },
+    Open { until: Instant, failures: u32 },
+    /// A trial that never reports back is given up on after `open_for`.
+    HalfOpen { since: Instant, failures: u32 },
+}
+
+impl CircuitBreaker {
+    pub fn new(threshold: u32, open_for: Duration) -> Self {
+        CircuitBreaker {
+            threshold: threshold.max(1),
+            open_for,
+            state: Mutex::new(BreakerState::Closed { failures: 0 }),
+        }
+    }
+
+    /// Whether a request may be sent now, a `true` must be followed by `record`.
+    pub fn allow(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        match *state {
+            BreakerState::Closed { .. } => true,
+            BreakerState::Open { until, failures } | BreakerState::HalfOpen { since: until, failures }
+                if now >= until =>
+            {
+                *state = BreakerState::HalfOpen {
+                    since: now + self.open_for,
+                    failures,
+                };
+                true
+            }
+            BreakerState::Open { .. } | BreakerState::HalfOpen { .. } => false,
+        }
+    }
+
+    /// Report the outcome of an allowed request, `healthy` unless the origin itself failed.
+    pub fn record(&self, healthy: bool) {
+        let mut state = self.state.lock().unwrap();
+        let failures = match *state {
+            BreakerState::Closed { failures }
+            | BreakerState::Open { failures, .. }
+            | BreakerState::HalfOpen { failures, .. } => failures,
+        };
+        *state = match (*state, healthy) {
+            (_, true) => BreakerState::Closed { failures: 0 },
+            (BreakerState::Closed { .. }, false) if failures + 1 < self.threshold => {
+                BreakerState::Closed { failures: failures + 1 }
+            }
+            (_, false) => BreakerState::Open {
+                until: Instant::now() + self.open_for,
+                failures: failures + 1,
+            },
+        };
+    }
+
+    pub fn status(&self) -> BreakerStatus {
+        let (state, consecutive_failures) = match *self.state.lock().unwrap() {
+            BreakerState::Closed { failures } => (CircuitState::Closed, failures),
+            BreakerState::Open { failures, .. } => (CircuitState::Open, failures),
+            BreakerState::HalfOpen { failures, .. } => (CircuitState::HalfOpen, failures),
+        };
+        BreakerStatus {
+            state,
+            consecutive_failures,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn breaker_opens_and_recovers() {
+        let breaker = CircuitBreaker::new(2, Duration::from_millis(20));
+        assert!(breaker.allow());
+        breaker.record(false);
+        assert_eq!(breaker.status().state, CircuitState::Closed);
+        breaker.record(false);
+        assert_eq!(breaker.status().state, CircuitState::Open);
+        assert!(!breaker.allow());
+
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(breaker.allow());
+        assert_eq!(breaker.status().state, CircuitState::HalfOpen);
+        assert!(!breaker.allow());
+        breaker.record(true);
+        assert_eq!(breaker.status(), BreakerStatus { state: CircuitState::Closed, consecutive_failures: 0 })
+    }
+
+    #[test]
+    fn failed_trial_reopens() {
+        let breaker = CircuitBreaker::new(1, Duration::from_millis(10));
+        breaker.record(false);
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(breaker.allow());
+        breaker.record(false);
+        assert_eq!(breaker.status().state, CircuitState::Open);
+        assert!(!breaker.allow())
+    }
+}
@@ -1 +1,203 @@
-pub(crate) mod bucket_client;
\ No newline at end of file
+use crate::domain::error::ErrorResponse;
+use crate::domain::error::ErrorResponse::{
+    ForbiddenSourceError, ImageNotFoundError, OriginUnavailableError, PayloadTooLargeError, SourceFetchError,
+};
+use crate::client::body::{read_body, BodyLimits};
+use crate::domain::image_item::OriginMetadata;
+use crate::domain::status::BreakerStatus;
+use async_trait::async_trait;
+use hyper::body::Bytes;
+use reqwest::header::{HeaderMap, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+use reqwest::{RequestBuilder, Response, StatusCode};
+use std::fmt::{Debug, Display, Formatter};
+
+pub(crate) mod body;
+pub(crate) mod bucket_client;
+pub(crate) mod circuit_breaker;
+pub(crate) mod origin_guard;
+pub(crate) mod remote_client;
+pub(crate) mod s3_client;
+pub(crate) mod sigv4;
+
+/// Outcome of a bucket request.
+pub enum BucketResponse {
+    Fetched(Bytes, OriginMetadata),
+    /// Only returned for conditional requests, the cached copy is still current.
+    NotModified,
+    /// The bucket confirmed there is no object at the path.
+    NotFound,
+}
+
+/// Object storage an `ObjectRepository` keeps its originals in.
+#[async_trait]
+pub trait ObjectClient: Debug + Send + Sync {
+    /// Name of the origin in logs and purge reports.
+    fn name(&self) -> &'static str;
+
+    async fn get(&self, path: &str) -> Result<BucketResponse, ClientError>;
+
+    /// Conditional GET using the validators of a previously fetched copy.
+    async fn revalidate(&self, path: &str, metadata: &OriginMetadata) -> Result<BucketResponse, ClientError>;
+
+    /// Store an object, replacing any previous version.
+    async fn put(&self, path: &str, body: Bytes, content_type: &str) -> Result<OriginMetadata, ClientError>;
+
+    fn status(&self) -> BreakerStatus;
+}
+
+/// Failure to get a usable response out of an origin.
+#[derive(Debug)]
+pub enum ClientError {
+    Http(reqwest::Error),
+    /// No token could be obtained for an authenticated request.
+    Auth(gcp_auth::Error),
+    /// The request was refused before being sent, e.g. a host that is not allowed.
+    Forbidden(String),
+    /// The response body exceeded the limit in bytes.
+    TooLarge(u64),
+    /// A large response body could not be written to disk.
+    Spool(std::io::Error),
+    Status(StatusCode),
+    Timeout,
+    /// The circuit breaker of the origin is open.
+    Unavailable,
+}
+
+impl ClientError {
+    /// Whether the origin itself failed, worth retrying and counted against its health.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            ClientError::Http(e) => e.is_connect() || e.is_timeout() || e.is_body(),
+            ClientError::Status(status) => status.is_server_error(),
+            ClientError::Timeout => true,
+            _ => false,
+        }
+    }
+}
+
+impl Display for ClientError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ClientError::Http(e) => write!(f, "Request failed: {e}"),
+            ClientError::Auth(e) => write!(f, "Authentication failed: {e}"),
+            ClientError::Forbidden(reason) => write!(f, "Request refused: {reason}"),
+            ClientError::TooLarge(limit) => write!(f, "Response larger than {limit} bytes"),
+            ClientError::Spool(e) => write!(f, "Could not spool response: {e}"),
+            ClientError::Status(status) => write!(f, "Unexpected status {status}"),
+            ClientError::Timeout => write!(f, "Timed out"),
+            ClientError::Unavailable => write!(f, "Origin unavailable, circuit open"),
+        }
+    }
+}
+
+impl From<reqwest::Error> for ClientError {
+    fn from(e: reqwest::Error) -> Self {
+        ClientError::Http(e)
+    }
+}
+
+impl From<gcp_auth::Error> for ClientError {
+    fn from(e: gcp_auth::Error) -> Self {
+        ClientError::Auth(e)
+    }
+}
+
+/// What the client of the service is told, a failing origin is a 502 unless it denied access
+/// or the original is over the size limit.
+impl From<ClientError> for ErrorResponse {
+    fn from(e: ClientError) -> Self {
+        match e {
+            ClientError::Status(StatusCode::NOT_FOUND) => ImageNotFoundError {},
+            ClientError::Status(StatusCode::FORBIDDEN) | ClientError::Forbidden(_) => ForbiddenSourceError {},
+            ClientError::Unavailable => OriginUnavailableError {},
+            ClientError::TooLarge(_) => PayloadTooLargeError {},
+            _ => SourceFetchError {},
+        }
+    }
+}
+
+/// Add the validators of a previously fetched copy to make the request conditional.
+pub(crate) fn conditional(mut request: RequestBuilder, metadata: &OriginMetadata) -> RequestBuilder {
+    if let Some(etag) = &metadata.etag {
+        request = request.header(IF_NONE_MATCH, etag);
+    }
+    if let Some(last_modified) = &metadata.last_modified {
+        request = request.header(IF_MODIFIED_SINCE, last_modified);
+    }
+    request
+}
+
+/// Read a bucket response, `generation_header` names the header carrying the object version.
+/// Anything but a 2xx, 304 or 404 is an error, so error bodies are never taken for images.
+pub(crate) async fn bucket_response(
+    resp: Response,
+    generation_header: &str,
+    limits: &BodyLimits,
+) -> Result<BucketResponse, ClientError> {
+    match resp.status() {
+        StatusCode::NOT_MODIFIED => return Ok(BucketResponse::NotModified),
+        StatusCode::NOT_FOUND => return Ok(BucketResponse::NotFound),
+        status if !status.is_success() => return Err(ClientError::Status(status)),
+        _ => {}
+    }
+    let metadata = origin_metadata(resp.headers(), Some(generation_header));
+    let bytes = read_body(resp, limits).await?;
+    Ok(BucketResponse::Fetched(bytes, metadata))
+}
+
+/// Read the response to an upload, the validators of the stored object.
+pub(crate) async fn stored_response(resp: Response, generation_header: &str) -> Result<OriginMetadata, ClientError> {
+    match resp.status() {
+        status if status.is_success() => Ok(origin_metadata(resp.headers(), Some(generation_header))),
+        status => Err(ClientError::Status(status)),
+    }
+}
+
+/// Validators of a response, `generation_header` names the header carrying the object version.
+pub(crate) fn origin_metadata(headers: &HeaderMap, generation_header: Option<&str>) -> OriginMetadata {
+    let header = |name: &str| headers.get(name).and_then(|v| v.to_str().ok()).map(String::from);
+    OriginMetadata::new(
+        header(ETAG.as_str()),
+        header(LAST_MODIFIED.as_str()),
+        generation_header.and_then(header),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn limits() -> BodyLimits {
+        BodyLimits {
+            max_bytes: 16,
+            spool_above: 16,
+            spool_dir: PathBuf::new(),
+        }
+    }
+
+    fn response(status: u16, body: &'static str) -> Response {
+        Response::from(
+            hyper::Response::builder()
+                .status(status)
+                .header(ETAG, "\"1\"")
+                .body(body)
+                .unwrap(),
+        )
+    }
+
+    #[tokio::test]
+    async fn bucket_response_rejects_error_statuses() {
+        let fetched = bucket_response(response(200, "image"), "x-goog-generation", &limits()).await.unwrap();
+        assert!(matches!(fetched, BucketResponse::Fetched(bytes, _) if bytes == "image"));
+        let not_found = bucket_response(response(404, "<Error/>"), "x-goog-generation", &limits()).await.unwrap();
+        assert!(matches!(not_found, BucketResponse::NotFound));
+
+        let forbidden = bucket_response(response(403, "<Error/>"), "x-goog-generation", &limits()).await;
+        assert!(matches!(forbidden.map_err(ErrorResponse::from), Err(ForbiddenSourceError {})));
+        let unavailable = bucket_response(response(503, "<Error/>"), "x-goog-generation", &limits()).await;
+        assert!(matches!(unavailable.map_err(ErrorResponse::from), Err(SourceFetchError {})));
+        let too_large = bucket_response(response(200, "a very large image"), "x-goog-generation", &limits()).await;
+        assert!(matches!(too_large.map_err(ErrorResponse::from), Err(PayloadTooLargeError {})))
+    }
+}
@@ -0,0 +1,194 @@
+use crate::client::body::BodyLimits;
+use crate::client::circuit_breaker::CircuitBreaker;
+use crate::client::ClientError;
+use crate::domain::status::BreakerStatus;
+use rand::Rng;
+use serde::Deserialize;
+use std::future::Future;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::time::{sleep, timeout};
+use tracing::warn;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct OriginConfig {
+    /// Limit on a single request, including reading the body.
+    pub attempt_timeout_ms: u64,
+    /// Limit on all attempts of a request together, including the waits between them.
+    pub total_timeout_ms: u64,
+    /// Further attempts after a 5xx or connection failure.
+    pub max_retries: u32,
+    /// Waits between attempts double from this, with full jitter.
+    pub backoff_base_ms: u64,
+    pub backoff_max_ms: u64,
+    /// Consecutive failed requests after which the origin is considered down.
+    pub breaker_threshold: u32,
+    /// How long requests to a down origin fail fast before one is let through to try it.
+    pub breaker_open_secs: u64,
+    /// Largest original accepted, larger ones are refused with 413.
+    pub max_object_bytes: u64,
+    /// Originals larger than this are spooled to `spool_dir` rather than held in memory.
+    pub spool_threshold_bytes: u64,
+    /// `<cache.volume_root>/spool` unless set, spooled files never outlive the request using them.
+    pub spool_dir: Option<String>,
+}
+
+impl Default for OriginConfig {
+    fn default() -> Self {
+        OriginConfig {
+            attempt_timeout_ms: 5000,
+            total_timeout_ms: 15000,
+            max_retries: 2,
+            backoff_base_ms: 100,
+            backoff_max_ms: 2000,
+            breaker_threshold: 5,
+            breaker_open_secs: 30,
+            max_object_bytes: 64 * 1024 * 1024,
+            spool_threshold_bytes: 8 * 1024 * 1024,
+            spool_dir: None,
+        }
+    }
+}
+
+impl OriginConfig {
+    pub fn body_limits(&self) -> BodyLimits {
+        BodyLimits {
+            max_bytes: self.max_object_bytes,
+            spool_above: self.spool_threshold_bytes,
+            spool_dir: self.spool_dir.as_ref().map(PathBuf::from).unwrap_or_else(std::env::temp_dir),
+        }
+    }
+}
+
+/// Timeouts, retries and a circuit breaker around the requests to one origin.
+#[derive(Debug)]
+pub struct OriginGuard {
+    config: OriginConfig,
+    breaker: CircuitBreaker,
+}
+
+impl OriginGuard {
+    pub fn new(config: OriginConfig) -> Self {
+        let breaker = CircuitBreaker::new(config.breaker_threshold, Duration::from_secs(config.breaker_open_secs));
+        OriginGuard { config, breaker }
+    }
+
+    /// Run an idempotent request, `attempt` is called again for every retry.
+    pub async fn call<T, F, Fut>(&self, attempt: F) -> Result<T, ClientError>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<T, ClientError>>,
+    {
+        if !self.breaker.allow() {
+            return Err(ClientError::Unavailable);
+        }
+        let total = Duration::from_millis(self.config.total_timeout_ms);
+        let result = timeout(total, self.retry(attempt))
+            .await
+            .unwrap_or(Err(ClientError::Timeout));
+        self.breaker
+            .record(!matches!(&result, Err(e) if e.is_transient()));
+        result
+    }
+
+    pub fn status(&self) -> BreakerStatus {
+        self.breaker.status()
+    }
+
+    async fn retry<T, F, Fut>(&self, attempt: F) -> Result<T, ClientError>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<T, ClientError>>,
+    {
+        let attempt_timeout = Duration::from_millis(self.config.attempt_timeout_ms);
+        let mut retries = 0;
+        loop {
+            let result = timeout(attempt_timeout, attempt())
+                .await
+                .unwrap_or(Err(ClientError::Timeout));
+            match result {
+                Err(e) if e.is_transient() && retries < self.config.max_retries => {
+                    let backoff = self.backoff(retries);
+                    warn!("Origin request failed, retrying in {} ms: {e}", backoff.as_millis());
+                    sleep(backoff).await;
+                    retries += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Full jitter, a random wait up to the exponential backoff for the retry.
+    fn backoff(&self, retry: u32) -> Duration {
+        let ceiling = self
+            .config
+            .backoff_base_ms
+            .saturating_mul(1 << retry.min(16))
+            .min(self.config.backoff_max_ms);
+        Duration::from_millis(rand::thread_rng().gen_range(0..=ceiling))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::status::CircuitState;
+    use reqwest::StatusCode;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    fn guard() -> OriginGuard {
+        OriginGuard::new(OriginConfig {
+            attempt_timeout_ms: 20,
+            backoff_base_ms: 1,
+            breaker_threshold: 2,
+            ..OriginConfig::default()
+        })
+    }
+
+    #[tokio::test]
+    async fn guard_retries_transient_failures() {
+        let guard = guard();
+        let attempts = AtomicU32::new(0);
+        let result = guard
+            .call(|| async {
+                match attempts.fetch_add(1, Ordering::SeqCst) {
+                    0 => Err(ClientError::Status(StatusCode::SERVICE_UNAVAILABLE)),
+                    1 => {
+                        sleep(Duration::from_millis(100)).await;
+                        Ok("late")
+                    }
+                    _ => Ok("image"),
+                }
+            })
+            .await;
+        assert_eq!(result.unwrap(), "image");
+        assert_eq!(attempts.load(Ordering::SeqCst), 3)
+    }
+
+    #[tokio::test]
+    async fn guard_does_not_retry_client_errors() {
+        let guard = guard();
+        let attempts = AtomicU32::new(0);
+        let result: Result<(), _> = guard
+            .call(|| async {
+                attempts.fetch_add(1, Ordering::SeqCst);
+                Err(ClientError::Status(StatusCode::FORBIDDEN))
+            })
+            .await;
+        assert!(matches!(result, Err(ClientError::Status(StatusCode::FORBIDDEN))));
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+        assert_eq!(guard.status().state, CircuitState::Closed)
+    }
+
+    #[tokio::test]
+    async fn guard_fails_fast_once_open() {
+        let guard = guard();
+        for _ in 0..2 {
+            let _: Result<(), _> = guard.call(|| async { Err(ClientError::Timeout) }).await;
+        }
+        assert_eq!(guard.status().state, CircuitState::Open);
+        let result: Result<(), _> = guard.call(|| async { Ok(()) }).await;
+        assert!(matches!(result, Err(ClientError::Unavailable)))
+    }
+}
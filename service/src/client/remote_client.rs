@@ -0,0 +1,274 @@
+use crate::client::body::{read_body, BodyLimits};
+use crate::client::origin_guard::OriginConfig;
+use crate::client::{origin_metadata, BucketResponse, ClientError};
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use reqwest::redirect::Policy;
+use reqwest::{StatusCode, Url};
+use serde::Deserialize;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::info;
+use url::Host;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RemoteConfig {
+    /// Hosts originals may be fetched from, `*.example.com` allows any subdomain of
+    /// `example.com`. Remote sources are disabled while the list is empty.
+    pub allowed_hosts: Vec<String>,
+    pub max_redirects: usize,
+    /// Largest original accepted, larger responses are abandoned mid-download.
+    pub max_bytes: u64,
+    /// Limit on the whole request, from connecting to reading the last byte.
+    pub timeout_secs: u64,
+}
+
+impl Default for RemoteConfig {
+    fn default() -> Self {
+        RemoteConfig {
+            allowed_hosts: Vec::new(),
+            max_redirects: 3,
+            max_bytes: 25 * 1024 * 1024,
+            timeout_secs: 10,
+        }
+    }
+}
+
+/// Client for originals hosted on third party servers. Only allowlisted hosts are asked,
+/// before and after redirects, and never at private, loopback or otherwise internal
+/// addresses, whatever their DNS records say. Large originals are spooled like those of
+/// the other origins, but refused above the remote limit.
+#[derive(Debug)]
+pub struct RemoteClient {
+    config: RemoteConfig,
+    http: reqwest::Client,
+    limits: BodyLimits,
+}
+
+impl RemoteClient {
+    pub fn new(config: RemoteConfig, origin: OriginConfig) -> Self {
+        info!("Initializing remote client for {} hosts.", config.allowed_hosts.len());
+        let allowed_hosts = config.allowed_hosts.clone();
+        let max_redirects = config.max_redirects;
+        let redirect = Policy::custom(move |attempt| {
+            if attempt.previous().len() > max_redirects {
+                attempt.error("too many redirects")
+            } else if let Err(reason) = check_url(&allowed_hosts, attempt.url()) {
+                attempt.error(reason)
+            } else {
+                attempt.follow()
+            }
+        });
+        let http = reqwest::Client::builder()
+            .use_rustls_tls()
+            .no_proxy()
+            .redirect(redirect)
+            .dns_resolver(Arc::new(PublicResolver))
+            .timeout(Duration::from_secs(config.timeout_secs))
+            .build()
+            .unwrap();
+        let limits = BodyLimits {
+            max_bytes: config.max_bytes,
+            ..origin.body_limits()
+        };
+        RemoteClient { config, http, limits }
+    }
+
+    pub async fn get(&self, url: &Url) -> Result<BucketResponse, ClientError> {
+        check_url(&self.config.allowed_hosts, url).map_err(ClientError::Forbidden)?;
+        let resp = self.http.get(url.clone()).send().await.map_err(refused)?;
+        match resp.status() {
+            StatusCode::NOT_FOUND => return Ok(BucketResponse::NotFound),
+            status if !status.is_success() => return Err(ClientError::Status(status)),
+            _ => {}
+        }
+        let metadata = origin_metadata(resp.headers(), None);
+        let bytes = read_body(resp, &self.limits).await?;
+        Ok(BucketResponse::Fetched(bytes, metadata))
+    }
+}
+
+/// Whether `url` may be requested, with the reason when it may not.
+fn check_url(allowed_hosts: &[String], url: &Url) -> Result<(), String> {
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(format!("scheme {} is not allowed", url.scheme()));
+    }
+    let Some(host) = url.host() else { return Err("no host".to_string()) };
+    let name = host.to_string();
+    if !allowed_hosts.iter().any(|allowed| host_matches(allowed, &name)) {
+        return Err(format!("host {name} is not allowed"));
+    }
+    // Literal addresses are connected to without going through the resolver.
+    let literal = match host {
+        Host::Ipv4(ip) => Some(IpAddr::V4(ip)),
+        Host::Ipv6(ip) => Some(IpAddr::V6(ip)),
+        Host::Domain(_) => None,
+    };
+    match literal {
+        Some(ip) if !is_public(ip) => Err(format!("address {ip} is not public")),
+        _ => Ok(()),
+    }
+}
+
+fn host_matches(allowed: &str, host: &str) -> bool {
+    let allowed = allowed.to_ascii_lowercase();
+    match allowed.strip_prefix("*.") {
+        Some(domain) => host.strip_suffix(domain).is_some_and(|sub| sub.ends_with('.') && sub.len() > 1),
+        None => allowed == host,
+    }
+}
+
+/// Surface requests the resolver or redirect policy refused as such rather than as
+/// failed connections.
+fn refused(e: reqwest::Error) -> ClientError {
+    let mut source = e.source();
+    while let Some(inner) = source {
+        if let Some(blocked) = inner.downcast_ref::<BlockedAddress>() {
+            return ClientError::Forbidden(blocked.to_string());
+        }
+        source = inner.source();
+    }
+    if e.is_redirect() {
+        return ClientError::Forbidden(e.to_string());
+    }
+    ClientError::Http(e)
+}
+
+#[derive(Debug)]
+struct BlockedAddress(String);
+
+impl Display for BlockedAddress {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} does not resolve to a public address", self.0)
+    }
+}
+
+impl Error for BlockedAddress {}
+
+/// Resolver only handing out public addresses, checked at connection time so a host
+/// cannot pass validation and then resolve somewhere internal.
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host((name.as_str(), 0))
+                .await?
+                .filter(|addr| is_public(addr.ip()))
+                .collect();
+            if addrs.is_empty() {
+                return Err(Box::new(BlockedAddress(name.as_str().to_string())) as Box<dyn Error + Send + Sync>);
+            }
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped().or_else(|| six_to_four(ip)) {
+            Some(embedded) => is_public_v4(embedded),
+            None => is_public_v6(ip),
+        },
+    }
+}
+
+/// The IPv4 address a 6to4 address, 2002::/16, routes to.
+fn six_to_four(ip: Ipv6Addr) -> Option<Ipv4Addr> {
+    let [first, high, low, ..] = ip.segments();
+    (first == 0x2002).then(|| Ipv4Addr::from((u32::from(high) << 16) | u32::from(low)))
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, c, _] = ip.octets();
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_multicast()
+        || a == 0
+        // Carrier-grade NAT, 100.64.0.0/10.
+        || (a == 100 && (b & 0xc0) == 64)
+        // IETF protocol assignments, 192.0.0.0/24.
+        || (a == 192 && b == 0 && c == 0)
+        // Benchmarking, 198.18.0.0/15.
+        || (a == 198 && (b & 0xfe) == 18)
+        // Reserved, 240.0.0.0/4.
+        || a >= 240)
+}
+
+fn is_public_v6(ip: Ipv6Addr) -> bool {
+    let first = ip.segments()[0];
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_multicast()
+        // Unique local, fc00::/7.
+        || (first & 0xfe00) == 0xfc00
+        // Link local, fe80::/10.
+        || (first & 0xffc0) == 0xfe80
+        // Documentation, 2001:db8::/32.
+        || (first == 0x2001 && ip.segments()[1] == 0x0db8)
+        // NAT64 and IPv4-compatible addresses embed an IPv4 address that may be internal.
+        || (first == 0x0064 && ip.segments()[1] == 0xff9b)
+        // Teredo, 2001::/32, tunnels to an obfuscated IPv4 address that cannot be checked.
+        || (first == 0x2001 && ip.segments()[1] == 0)
+        || ip.segments()[..6].iter().all(|segment| *segment == 0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn allowed() -> Vec<String> {
+        vec!["images.example.com".to_string(), "*.cdn.example.net".to_string(), "127.0.0.1".to_string()]
+    }
+
+    fn check(url: &str) -> Result<(), String> {
+        check_url(&allowed(), &Url::parse(url).unwrap())
+    }
+
+    #[test]
+    fn only_allowed_hosts_pass() {
+        assert!(check("https://images.example.com/a.jpg").is_ok());
+        assert!(check("https://eu.cdn.example.net/a.jpg").is_ok());
+        assert!(check("https://cdn.example.net/a.jpg").is_err());
+        assert!(check("https://evilcdn.example.net/a.jpg").is_err());
+        assert!(check("https://images.example.com.evil.com/a.jpg").is_err());
+        assert!(check("ftp://images.example.com/a.jpg").is_err())
+    }
+
+    #[test]
+    fn internal_addresses_are_blocked() {
+        assert!(check("http://127.0.0.1/a.jpg").is_err());
+        for ip in ["10.0.0.1", "172.16.0.1", "192.168.1.1", "169.254.169.254", "100.64.0.1", "0.0.0.0"] {
+            assert!(!is_public(ip.parse().unwrap()), "{ip} passed as public");
+        }
+        for ip in ["::1", "fd00::1", "fe80::1", "::ffff:127.0.0.1", "::ffff:10.0.0.1"] {
+            assert!(!is_public(ip.parse().unwrap()), "{ip} passed as public");
+        }
+        // 6to4 towards loopback and the metadata service, and Teredo.
+        for ip in ["2002:7f00:1::", "2002:a9fe:a9fe::1", "2001:0:4136:e378::1"] {
+            assert!(!is_public(ip.parse().unwrap()), "{ip} passed as public");
+        }
+        assert!(is_public("93.184.216.34".parse().unwrap()));
+        assert!(is_public("2002:5db8:d822::1".parse().unwrap()));
+        assert!(is_public("2606:2800:220:1::".parse().unwrap()))
+    }
+
+    #[tokio::test]
+    async fn resolver_refuses_internal_names() {
+        let config = RemoteConfig {
+            allowed_hosts: vec!["localhost".to_string()],
+            ..RemoteConfig::default()
+        };
+        let client = RemoteClient::new(config, OriginConfig::default());
+        let url = Url::parse("http://localhost:1/a.jpg").unwrap();
+        assert!(matches!(client.get(&url).await, Err(ClientError::Forbidden(_))))
+    }
+}
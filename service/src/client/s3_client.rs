@@ -0,0 +1,222 @@
+use crate::client::body::BodyLimits;
+use crate::client::origin_guard::{OriginConfig, OriginGuard};
+use crate::client::sigv4::{amz_date, authorization, canonical_uri, sha256_hex, Credentials, SigningRequest};
+use crate::client::{bucket_response, conditional, stored_response, BucketResponse, ClientError, ObjectClient};
+use crate::domain::image_item::OriginMetadata;
+use crate::domain::status::BreakerStatus;
+use async_trait::async_trait;
+use chrono::Utc;
+use hyper::body::Bytes;
+use reqwest::header::CONTENT_TYPE;
+use reqwest::{Method, RequestBuilder, Url};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use tracing::info;
+
+const VERSION_HEADER: &str = "x-amz-version-id";
+const SERVICE: &str = "s3";
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct S3Config {
+    /// e.g. `https://s3.eu-west-1.amazonaws.com` or `http://minio:9000`.
+    pub endpoint: String,
+    pub region: String,
+    pub bucket: String,
+    /// Address the bucket as `<endpoint>/<bucket>` rather than `<bucket>.<endpoint host>`,
+    /// which MinIO and Ceph usually need.
+    pub path_style: bool,
+}
+
+impl Default for S3Config {
+    fn default() -> Self {
+        S3Config {
+            endpoint: "https://s3.amazonaws.com".to_string(),
+            region: "us-east-1".to_string(),
+            bucket: String::new(),
+            path_style: false,
+        }
+    }
+}
+
+/// Client for S3-compatible object storage, signing requests with SigV4 when credentials
+/// are present in the environment and sending them anonymously otherwise.
+#[derive(Debug)]
+pub struct S3Client {
+    config: S3Config,
+    credentials: Option<Credentials>,
+    http: reqwest::Client,
+    guard: OriginGuard,
+    limits: BodyLimits,
+}
+
+impl S3Client {
+    pub fn new(config: S3Config, credentials: Option<Credentials>, origin: OriginConfig) -> Self {
+        info!("Initializing S3 client for bucket {}.", config.bucket);
+        let http = reqwest::Client::builder()
+            .use_rustls_tls()
+            .build()
+            .unwrap();
+        S3Client {
+            config,
+            credentials,
+            http,
+            limits: origin.body_limits(),
+            guard: OriginGuard::new(origin),
+        }
+    }
+}
+
+#[async_trait]
+impl ObjectClient for S3Client {
+    fn name(&self) -> &'static str {
+        "s3"
+    }
+
+    async fn get(&self, path: &str) -> Result<BucketResponse, ClientError> {
+        self.guard
+            .call(|| async {
+                let resp = self.request(Method::GET, path, Bytes::new()).send().await?;
+                bucket_response(resp, VERSION_HEADER, &self.limits).await
+            })
+            .await
+    }
+
+    async fn revalidate(&self, path: &str, metadata: &OriginMetadata) -> Result<BucketResponse, ClientError> {
+        self.guard
+            .call(|| async {
+                let resp = conditional(self.request(Method::GET, path, Bytes::new()), metadata).send().await?;
+                bucket_response(resp, VERSION_HEADER, &self.limits).await
+            })
+            .await
+    }
+
+    async fn put(&self, path: &str, body: Bytes, content_type: &str) -> Result<OriginMetadata, ClientError> {
+        self.guard
+            .call(|| async {
+                let resp = self
+                    .request(Method::PUT, path, body.clone())
+                    .header(CONTENT_TYPE, content_type)
+                    .send()
+                    .await?;
+                stored_response(resp, VERSION_HEADER).await
+            })
+            .await
+    }
+
+    fn status(&self) -> BreakerStatus {
+        self.guard.status()
+    }
+}
+
+impl S3Client {
+    /// Build a signed request for the object at `path`, further headers added to the
+    /// builder are not signed.
+    pub fn request(&self, method: Method, path: &str, body: Bytes) -> RequestBuilder {
+        let url = self.object_url(path);
+        let mut headers = BTreeMap::from([
+            ("host".to_string(), host_header(&url)),
+            ("x-amz-content-sha256".to_string(), sha256_hex(&body)),
+            ("x-amz-date".to_string(), amz_date(Utc::now())),
+        ]);
+        if let Some(token) = self.credentials.as_ref().and_then(|c| c.session_token.clone()) {
+            headers.insert("x-amz-security-token".to_string(), token);
+        }
+        let mut request = self.http.request(method.clone(), url.clone());
+        if let Some(credentials) = &self.credentials {
+            let signing_request = SigningRequest {
+                method: method.as_str(),
+                canonical_uri: url.path(),
+                canonical_query: "",
+                headers: &headers,
+                payload_hash: &headers["x-amz-content-sha256"],
+            };
+            let authorization = authorization(
+                &signing_request,
+                credentials,
+                &self.config.region,
+                SERVICE,
+                &headers["x-amz-date"],
+            );
+            request = request.header("authorization", authorization);
+        }
+        for (name, value) in headers.iter().filter(|(name, _)| *name != "host") {
+            request = request.header(name, value);
+        }
+        request.body(body)
+    }
+
+    fn object_url(&self, path: &str) -> Url {
+        let mut url = Url::parse(&self.config.endpoint).expect("S3 endpoint must be a valid URL");
+        let key = canonical_uri(path);
+        if self.config.path_style {
+            let base = url.path().trim_end_matches('/').to_string();
+            url.set_path(&format!("{base}/{}{key}", self.config.bucket));
+        } else {
+            let host = format!("{}.{}", self.config.bucket, url.host_str().unwrap_or_default());
+            url.set_host(Some(&host)).expect("S3 bucket must form a valid host name");
+            url.set_path(&key);
+        }
+        url
+    }
+}
+
+fn host_header(url: &Url) -> String {
+    let host = url.host_str().unwrap_or_default();
+    match url.port() {
+        Some(port) => format!("{host}:{port}"),
+        None => host.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn client(path_style: bool) -> S3Client {
+        S3Client::new(
+            S3Config {
+                endpoint: "http://localhost:9000".to_string(),
+                region: "us-east-1".to_string(),
+                bucket: "images".to_string(),
+                path_style,
+            },
+            Some(Credentials {
+                access_key_id: "minioadmin".to_string(),
+                secret_access_key: "minioadmin".to_string(),
+                session_token: None,
+            }),
+            OriginConfig::default(),
+        )
+    }
+
+    #[test]
+    fn object_url_addressing() {
+        assert_eq!(client(true).object_url("/a b.jpg").as_str(), "http://localhost:9000/images/a%20b.jpg");
+        assert_eq!(client(false).object_url("/a.jpg").as_str(), "http://images.localhost:9000/a.jpg")
+    }
+
+    /// Round trip against the MinIO service in docker-compose.yaml:
+    /// `docker compose up minio` then `cargo test -- --ignored`.
+    #[tokio::test]
+    #[ignore = "needs a local MinIO"]
+    async fn minio_round_trip() {
+        let client = client(true);
+        let _ = client.request(Method::PUT, "", Bytes::new()).send().await;
+        let put = client
+            .request(Method::PUT, "/fixtures/pixel.bin", Bytes::from_static(b"pixel"))
+            .send()
+            .await
+            .unwrap();
+        assert!(put.status().is_success(), "PUT failed with {}", put.status());
+
+        match client.get("/fixtures/pixel.bin").await.unwrap() {
+            BucketResponse::Fetched(bytes, metadata) => {
+                assert_eq!(bytes, "pixel");
+                assert!(metadata.etag.is_some())
+            }
+            _ => panic!("Expected the object back"),
+        }
+        assert!(matches!(client.get("/fixtures/missing.bin").await.unwrap(), BucketResponse::NotFound))
+    }
+}
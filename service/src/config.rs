@@ -0,0 +1,216 @@
+use crate::client::bucket_client::BucketConfig;
+use crate::client::origin_guard::OriginConfig;
+use crate::client::remote_client::RemoteConfig;
+use crate::client::s3_client::S3Config;
+use crate::domain::cache_policy::CacheControlConfig;
+use crate::domain::dimension::DimensionPolicy;
+use crate::domain::image_limits::ImageLimits;
+use crate::domain::imgproxy::ImgproxyConfig;
+use crate::domain::preset::Preset;
+use crate::domain::thumbor::ThumborConfig;
+use crate::domain::upload::UploadConfig;
+use crate::repository::chain::LayerSpec;
+use crate::repository::filesystem_repository::FilesystemConfig;
+use crate::repository::mount::MountConfig;
+use crate::repository::volume_repository::SPOOL_DIR;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::time::Duration;
+use tracing::info;
+
+const CONFIG_PATH_ENV: &str = "CONFIG_PATH";
+
+/// Service configuration, read from the TOML file named by `CONFIG_PATH`.
+/// Every field has a default so the service runs without any file at all.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub cache: CacheConfig,
+    pub cache_control: CacheControlConfig,
+    pub repository: RepositoryConfig,
+    /// Path prefixes served by their own origin and caches instead of the repository chain.
+    pub mounts: Vec<MountConfig>,
+    /// Timeouts, retries and circuit breaking of the bucket and S3 origins.
+    pub origin: OriginConfig,
+    pub bucket: BucketConfig,
+    pub filesystem: FilesystemConfig,
+    pub remote: RemoteConfig,
+    pub upload: UploadConfig,
+    /// Pixel, memory and output dimension limits of every transform.
+    pub limits: ImageLimits,
+    /// Widths and heights served, to bound the variants cached per image.
+    pub dimensions: DimensionPolicy,
+    /// Named transforms, requested as `?preset=<name>` or `/p/<name>/<path>`.
+    pub presets: BTreeMap<String, Preset>,
+    /// Serving of Thumbor-style URLs.
+    pub thumbor: ThumborConfig,
+    /// Serving of imgproxy-style URLs.
+    pub imgproxy: ImgproxyConfig,
+    pub s3: S3Config,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct RepositoryConfig {
+    /// Layers images are looked up in, caches first and origins last.
+    pub chain: Vec<LayerSpec>,
+}
+
+impl Default for RepositoryConfig {
+    fn default() -> Self {
+        RepositoryConfig {
+            chain: vec![LayerSpec::Volume, LayerSpec::Bucket],
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct CacheConfig {
+    /// Directory of the shared volume holding cached originals.
+    pub volume_root: String,
+    /// Move entries from the old path-mirroring layout into the hashed layout at startup.
+    pub migrate_legacy_layout: bool,
+    /// Age in seconds after which a cached original is revalidated against the bucket.
+    pub revalidate_after_secs: u64,
+    /// How long a confirmed origin 404 is remembered, 0 disables negative caching.
+    pub negative_ttl_secs: u64,
+    /// Maximum number of remembered 404s, the oldest are dropped first.
+    pub negative_capacity: usize,
+    /// Maximum number of cache writes waiting in the background, further writes are dropped.
+    pub write_queue_depth: usize,
+    /// Size limit of all in-memory cache layers together, see `Config::memory_layer_bytes`.
+    pub memory_max_bytes: u64,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        CacheConfig {
+            volume_root: "/mnt/shared-cache".to_string(),
+            migrate_legacy_layout: false,
+            revalidate_after_secs: 3600,
+            negative_ttl_secs: 60,
+            negative_capacity: 10000,
+            write_queue_depth: 64,
+            memory_max_bytes: 256 * 1024 * 1024,
+        }
+    }
+}
+
+impl CacheConfig {
+    pub fn revalidate_after(&self) -> Duration {
+        Duration::from_secs(self.revalidate_after_secs)
+    }
+
+    pub fn negative_ttl(&self) -> Duration {
+        Duration::from_secs(self.negative_ttl_secs)
+    }
+}
+
+impl Config {
+    /// Load the configuration, panicking on an unreadable or invalid file as the
+    /// service cannot run in a sensible way with half a config.
+    pub fn load() -> Config {
+        match std::env::var(CONFIG_PATH_ENV) {
+            Ok(path) => {
+                info!("Loading config from {path}");
+                let contents = std::fs::read_to_string(&path)
+                    .unwrap_or_else(|e| panic!("Could not read config at {path}: {e}"));
+                Config::parse(&contents)
+                    .unwrap_or_else(|e| panic!("Could not parse config at {path}: {e}"))
+            }
+            Err(_) => {
+                info!("No {CONFIG_PATH_ENV} set, using default config.");
+                Config::default().with_derived_defaults()
+            }
+        }
+    }
+
+    pub fn parse(contents: &str) -> Result<Config, toml::de::Error> {
+        toml::from_str(contents).map(Config::with_derived_defaults)
+    }
+
+    /// Fill in the settings whose defaults depend on others.
+    fn with_derived_defaults(mut self) -> Config {
+        if self.origin.spool_dir.is_none() {
+            let spool_dir = Path::new(&self.cache.volume_root).join(SPOOL_DIR);
+            self.origin.spool_dir = Some(spool_dir.to_string_lossy().into_owned());
+        }
+        self
+    }
+
+    /// Size limit of each "memory" layer. `cache.memory_max_bytes` is split evenly between
+    /// the repository chain and the mounts that have one, so together they stay within it.
+    pub fn memory_layer_bytes(&self) -> u64 {
+        let chains = self.mounts.iter().map(|mount| &mount.caches).chain([&self.repository.chain]);
+        let layers = chains.flatten().filter(|layer| **layer == LayerSpec::Memory).count();
+        self.cache.memory_max_bytes / layers.max(1) as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn config_defaults_when_empty() {
+        let config = Config::parse("").unwrap();
+        assert_eq!(config.cache.revalidate_after_secs, 3600)
+    }
+
+    #[test]
+    fn config_parses_cache_section() {
+        let config = Config::parse("[cache]\nrevalidate_after_secs = 60\n").unwrap();
+        assert_eq!(config.cache.revalidate_after(), Duration::from_secs(60))
+    }
+
+    #[test]
+    fn spool_dir_defaults_to_the_volume_root() {
+        let config = Config::parse("[cache]\nvolume_root = \"/data/cache\"\n").unwrap();
+        assert_eq!(config.origin.spool_dir.as_deref(), Some("/data/cache/spool"));
+        let config = Config::parse("[origin]\nspool_dir = \"/tmp/spool\"\n").unwrap();
+        assert_eq!(config.origin.spool_dir.as_deref(), Some("/tmp/spool"))
+    }
+
+    #[test]
+    fn config_parses_repository_chain() {
+        let config = Config::parse("[repository]\nchain = [\"memory\", \"bucket\"]\n").unwrap();
+        assert_eq!(config.repository.chain, vec![LayerSpec::Memory, LayerSpec::Bucket]);
+        assert!(Config::parse("[repository]\nchain = [\"nope\"]\n").is_err())
+    }
+
+    #[test]
+    fn config_parses_mounts() {
+        let config = Config::parse(
+            "[[mounts]]\nprefix = \"/shop/*\"\norigin = \"s3:bucket-b\"\n[mounts.transform]\nmax_width = 1920\n",
+        )
+        .unwrap();
+        assert_eq!(config.mounts[0].caches, vec![LayerSpec::Volume]);
+        assert_eq!(config.mounts[0].transform.max_width, Some(1920));
+        assert!(Config::parse("[[mounts]]\nprefix = \"/a/\"\norigin = \"ftp:a\"\n").is_err())
+    }
+
+    #[test]
+    fn memory_budget_is_split_between_layers() {
+        let config = Config::parse(
+            "[cache]\nmemory_max_bytes = 900\n[repository]\nchain = [\"memory\", \"bucket\"]\n\
+             [[mounts]]\nprefix = \"/a/\"\norigin = \"fs:/a\"\ncaches = [\"memory\", \"volume\"]\n\
+             [[mounts]]\nprefix = \"/b/\"\norigin = \"fs:/b\"\ncaches = [\"memory\"]\n\
+             [[mounts]]\nprefix = \"/c/\"\norigin = \"fs:/c\"\n",
+        )
+        .unwrap();
+        assert_eq!(config.memory_layer_bytes(), 300);
+        assert_eq!(Config::parse("[cache]\nmemory_max_bytes = 900\n").unwrap().memory_layer_bytes(), 900)
+    }
+
+    #[test]
+    fn config_parses_presets() {
+        let config = Config::parse("[presets.thumb]\nwidth = 160\nfit = \"cover\"\nfilters = [\"blur:1.5\"]\n").unwrap();
+        assert_eq!(config.presets["thumb"].width, Some(160));
+        assert!(Config::parse("[presets.thumb]\nfilters = [\"sepia\"]\n").is_err());
+        assert!(Config::parse("[presets.thumb]\nquality = 0\n").is_err());
+        assert!(Config::parse("[presets.thumb]\nwidth = 160\nheight = 160\n").is_err());
+        assert!(Config::parse("[presets.thumb]\nsize = 160\n").is_err())
+    }
+}
@@ -0,0 +1,200 @@
+use serde::Deserialize;
+
+/// Directives for the `Cache-Control` header of a response.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct CachePolicy {
+    pub max_age: Option<u64>,
+    pub s_maxage: Option<u64>,
+    pub stale_while_revalidate: Option<u64>,
+    pub immutable: bool,
+    pub private: bool,
+    pub no_store: bool,
+}
+
+impl CachePolicy {
+    pub fn max_age(seconds: u64) -> Self {
+        CachePolicy {
+            max_age: Some(seconds),
+            ..Default::default()
+        }
+    }
+
+    pub fn no_store() -> Self {
+        CachePolicy {
+            no_store: true,
+            ..Default::default()
+        }
+    }
+
+    pub fn cache_control(&self) -> String {
+        if self.no_store {
+            return "no-store".to_string();
+        }
+        let mut directives: Vec<String> = Vec::new();
+        if self.private {
+            directives.push("private".to_string());
+        }
+        match self.max_age {
+            Some(max_age) => directives.push(format!("max-age={max_age}")),
+            None => directives.push("no-cache".to_string()),
+        }
+        if let (Some(s_maxage), false) = (self.s_maxage, self.private) {
+            directives.push(format!("s-maxage={s_maxage}"));
+        }
+        if let Some(swr) = self.stale_while_revalidate {
+            directives.push(format!("stale-while-revalidate={swr}"));
+        }
+        if self.immutable {
+            directives.push("immutable".to_string());
+        }
+        directives.join(", ")
+    }
+
+    /// Value for a CDN targeted header, which only shared caches read so `s-maxage` becomes the max age.
+    pub fn surrogate_control(&self) -> String {
+        if self.no_store || self.private {
+            return "no-store".to_string();
+        }
+        match self.s_maxage.or(self.max_age) {
+            Some(max_age) => match self.stale_while_revalidate {
+                Some(swr) => format!("max-age={max_age}, stale-while-revalidate={swr}"),
+                None => format!("max-age={max_age}"),
+            },
+            None => "no-store".to_string(),
+        }
+    }
+}
+
+/// Header used to send a separate policy to the CDN.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub enum SurrogateHeader {
+    #[serde(rename = "Surrogate-Control")]
+    SurrogateControl,
+    #[serde(rename = "CDN-Cache-Control")]
+    CdnCacheControl,
+}
+
+impl SurrogateHeader {
+    pub fn header_name(&self) -> &'static str {
+        match self {
+            SurrogateHeader::SurrogateControl => "surrogate-control",
+            SurrogateHeader::CdnCacheControl => "cdn-cache-control",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RoutePolicy {
+    pub prefix: String,
+    #[serde(flatten)]
+    pub policy: CachePolicy,
+}
+
+/// Which kind of response a policy is picked for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Outcome {
+    Success,
+    /// A snapped request sent on to the URL of the size served.
+    Redirect,
+    NotFound,
+    Error,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct CacheControlConfig {
+    /// Policy for successful responses not matched by any route.
+    pub default: CachePolicy,
+    /// Per path prefix policies for successful responses, the longest matching prefix wins.
+    pub routes: Vec<RoutePolicy>,
+    /// Kept short, the size a request snaps to changes with the dimension policy.
+    pub redirects: CachePolicy,
+    pub not_found: CachePolicy,
+    pub errors: CachePolicy,
+    pub surrogate_header: Option<SurrogateHeader>,
+}
+
+impl Default for CacheControlConfig {
+    fn default() -> Self {
+        CacheControlConfig {
+            default: CachePolicy::max_age(31536000),
+            routes: Vec::new(),
+            redirects: CachePolicy::max_age(300),
+            not_found: CachePolicy::max_age(60),
+            errors: CachePolicy::no_store(),
+            surrogate_header: None,
+        }
+    }
+}
+
+impl CacheControlConfig {
+    pub fn policy_for(&self, path: &str, outcome: Outcome) -> &CachePolicy {
+        match outcome {
+            Outcome::Success => self
+                .routes
+                .iter()
+                .filter(|route| path.starts_with(&route.prefix))
+                .max_by_key(|route| route.prefix.len())
+                .map(|route| &route.policy)
+                .unwrap_or(&self.default),
+            Outcome::Redirect => &self.redirects,
+            Outcome::NotFound => &self.not_found,
+            Outcome::Error => &self.errors,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cache_control_renders_directives() {
+        let policy = CachePolicy {
+            max_age: Some(60),
+            s_maxage: Some(3600),
+            stale_while_revalidate: Some(30),
+            immutable: true,
+            ..Default::default()
+        };
+        assert_eq!(policy.cache_control(), "max-age=60, s-maxage=3600, stale-while-revalidate=30, immutable");
+        assert_eq!(policy.surrogate_control(), "max-age=3600, stale-while-revalidate=30");
+        assert_eq!(CachePolicy::no_store().cache_control(), "no-store");
+    }
+
+    #[test]
+    fn private_policy_skips_shared_caches() {
+        let policy = CachePolicy {
+            max_age: Some(60),
+            s_maxage: Some(3600),
+            private: true,
+            ..Default::default()
+        };
+        assert_eq!(policy.cache_control(), "private, max-age=60");
+        assert_eq!(policy.surrogate_control(), "no-store")
+    }
+
+    #[test]
+    fn policy_for_picks_longest_prefix() {
+        let config: CacheControlConfig = toml::from_str(
+            r#"
+            surrogate_header = "CDN-Cache-Control"
+            [[routes]]
+            prefix = "/static/"
+            max_age = 10
+            [[routes]]
+            prefix = "/static/icons/"
+            max_age = 20
+            immutable = true
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.policy_for("/static/icons/a.png", Outcome::Success).max_age, Some(20));
+        assert_eq!(config.policy_for("/static/a.png", Outcome::Success).max_age, Some(10));
+        assert_eq!(config.policy_for("/a.png", Outcome::Success).max_age, Some(31536000));
+        assert!(config.policy_for("/static/a.png", Outcome::Error).no_store);
+        assert_eq!(config.policy_for("/static/a.png", Outcome::Redirect).max_age, Some(300));
+        assert_eq!(config.surrogate_header, Some(SurrogateHeader::CdnCacheControl))
+    }
+}
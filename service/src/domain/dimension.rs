@@ -1,47 +1,181 @@
-use crate::domain::dimension::Dimension::{Height, Width};
+use crate::domain::dimension::Dimension::{Bounds, Height, Width};
 use crate::domain::error::ErrorResponse;
-use crate::domain::error::ErrorResponse::ImageWriteError;
-use std::collections::HashMap;
+use crate::domain::error::ErrorResponse::{DimensionNotAllowedError, ImageWriteError};
+use crate::domain::image_limits::ImageLimits;
+use crate::domain::query::{encode_query, QueryParams};
+use serde::Deserialize;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Dimension {
     Height(u32),
     Width(u32),
+    /// Both sides given, how the image is fitted into them is up to the `Fit` of the transform.
+    Bounds { width: u32, height: u32 },
 }
 
-pub fn decode(query: &str) -> Result<Dimension, ErrorResponse> {
-    let params: HashMap<&str, &str> = query
-        .split('&')
-        .collect::<Vec<&str>>()
-        .iter()
-        .filter_map(|pair| {
-            let some: Vec<&str> = pair.split('=').collect();
-            match some.len() {
-                2 => {
-                    let couple: (&str, &str) = (some.first()?, some.get(1)?);
-                    Some(couple)
-                }
-                _ => None,
-            }
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DimensionMode {
+    /// Any size within the limits.
+    #[default]
+    Any,
+    /// Only the listed sizes, others are rejected.
+    Allowlist,
+    /// Sizes are moved to the nearest listed one.
+    Snap,
+}
+
+/// Which widths and heights are served, to keep the number of variants per image down.
+/// An empty list leaves that side unrestricted.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct DimensionPolicy {
+    pub mode: DimensionMode,
+    pub widths: Vec<u32>,
+    pub heights: Vec<u32>,
+    /// Redirect snapped requests to the URL of the size they were snapped to, so every
+    /// variant is cached under a single URL. Not while URL signing is on, as the redirect
+    /// could not carry a valid signature.
+    pub redirect: bool,
+}
+
+impl DimensionPolicy {
+    /// The dimension to serve for a request, `None` if it is not allowed.
+    pub fn apply(&self, dimension: Dimension) -> Option<Dimension> {
+        Some(match dimension {
+            Width(width) => Width(self.apply_side(width, &self.widths)?),
+            Height(height) => Height(self.apply_side(height, &self.heights)?),
+            Bounds { width, height } => Bounds {
+                width: self.apply_side(width, &self.widths)?,
+                height: self.apply_side(height, &self.heights)?,
+            },
         })
-        .collect::<HashMap<&str, &str>>();
+    }
+
+    fn apply_side(&self, size: u32, sizes: &[u32]) -> Option<u32> {
+        if sizes.is_empty() {
+            return Some(size);
+        }
+        match self.mode {
+            DimensionMode::Any => Some(size),
+            DimensionMode::Allowlist => sizes.contains(&size).then_some(size),
+            // The larger of two equally near sizes, rather than serving a blurrier image.
+            DimensionMode::Snap => sizes
+                .iter()
+                .copied()
+                .min_by_key(|candidate| (candidate.abs_diff(size), std::cmp::Reverse(*candidate))),
+        }
+    }
+}
 
-    let opt_width = params.get("width");
-    let opt_height = params.get("height");
+/// A requested dimension once the `DimensionPolicy` is applied.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecodedDimension {
+    pub dimension: Dimension,
+    /// Whether the request asked for another size and was snapped to this one.
+    pub snapped: bool,
+}
 
-    match opt_width {
-        Some(w) => {
-            let a = str::parse::<u32>(w).map_err(|_| ImageWriteError {})?;
-            Ok(Width(a))
+/// Parse the requested dimension, refusing sizes beyond the output limits or outside the policy.
+pub fn decode(params: &QueryParams, limits: &ImageLimits, policy: &DimensionPolicy) -> Result<DecodedDimension, ErrorResponse> {
+    let requested = decode_requested(params, limits)?;
+    let dimension = policy.apply(requested.clone()).ok_or(DimensionNotAllowedError {})?;
+    Ok(DecodedDimension {
+        snapped: dimension != requested,
+        dimension,
+    })
+}
+
+/// `params` with the width or height replaced by that of `dimension`, the canonical query of a
+/// snapped request.
+pub fn canonical_query(params: &QueryParams, dimension: &Dimension) -> String {
+    let mut params = params.clone();
+    let (width, height) = match *dimension {
+        Width(width) => (Some(width), None),
+        Height(height) => (None, Some(height)),
+        Bounds { width, height } => (Some(width), Some(height)),
+    };
+    for (name, side) in [("width", width), ("height", height)] {
+        if let Some(side) = side {
+            params.insert(name.to_string(), side.to_string());
         }
-        None => match opt_height {
-            Some(h) => {
-                let a = str::parse::<u32>(h).map_err(|_| ImageWriteError {})?;
-                Ok(Height(a))
-            }
-            None => {
-                Err(ImageWriteError {})
-            }
-        },
+    }
+    encode_query(&params)
+}
+
+fn decode_requested(params: &QueryParams, limits: &ImageLimits) -> Result<Dimension, ErrorResponse> {
+    let opt_width = params.get("width").map(|w| parse_side(w, limits.max_output_width)).transpose()?;
+    // Both sides only count together with a `fit`, without one the width wins as it always has.
+    let opt_height = match (opt_width, params.contains_key("fit")) {
+        (Some(_), false) => None,
+        _ => params.get("height").map(|h| parse_side(h, limits.max_output_height)).transpose()?,
+    };
+
+    match (opt_width, opt_height) {
+        (Some(width), Some(height)) => Ok(Bounds { width, height }),
+        (Some(width), None) => Ok(Width(width)),
+        (None, Some(height)) => Ok(Height(height)),
+        (None, None) => Err(ImageWriteError {}),
+    }
+}
+
+fn parse_side(side: &str, max: u32) -> Result<u32, ErrorResponse> {
+    let a = str::parse::<u32>(side).map_err(|_| ImageWriteError {})?;
+    if a > max {
+        return Err(DimensionNotAllowedError {});
+    }
+    Ok(a)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::query::query_params;
+
+    fn policy(mode: DimensionMode) -> DimensionPolicy {
+        DimensionPolicy {
+            mode,
+            widths: vec![160, 320, 640, 1280, 1920],
+            heights: Vec::new(),
+            redirect: false,
+        }
+    }
+
+    fn decoded(query: &str, policy: &DimensionPolicy) -> Result<DecodedDimension, ErrorResponse> {
+        decode(&query_params(query), &ImageLimits::default(), policy)
+    }
+
+    #[test]
+    fn allowlist_rejects_unlisted_sizes() {
+        let policy = policy(DimensionMode::Allowlist);
+        assert_eq!(decoded("width=320", &policy).unwrap().dimension, Width(320));
+        assert!(matches!(decoded("width=321", &policy), Err(DimensionNotAllowedError {})));
+        assert_eq!(decoded("height=321", &policy).unwrap().dimension, Height(321))
+    }
+
+    #[test]
+    fn both_sides_need_a_fit() {
+        let policy = DimensionPolicy::default();
+        assert_eq!(decoded("width=300&height=90", &policy).unwrap().dimension, Width(300));
+        assert_eq!(decoded("width=300&height=nope", &policy).unwrap().dimension, Width(300));
+        let bounds = decoded("width=300&height=90&fit=cover", &policy).unwrap().dimension;
+        assert_eq!(bounds, Bounds { width: 300, height: 90 })
+    }
+
+    #[test]
+    fn snap_moves_to_nearest_breakpoint() {
+        let policy = policy(DimensionMode::Snap);
+        let snapped = decoded("width=500", &policy).unwrap();
+        assert_eq!((snapped.dimension, snapped.snapped), (Width(640), true));
+        assert_eq!(decoded("width=480", &policy).unwrap().dimension, Width(640));
+        assert_eq!(decoded("width=4000", &policy).unwrap().dimension, Width(1920));
+        assert!(!decoded("width=160", &policy).unwrap().snapped)
+    }
+
+    #[test]
+    fn canonical_query_replaces_the_size() {
+        assert_eq!(canonical_query(&query_params("width=500&url=x"), &Width(640)), "url=x&width=640");
+        let bounds = Bounds { width: 320, height: 90 };
+        assert_eq!(canonical_query(&query_params("height=90&width=300"), &bounds), "height=90&width=320")
     }
 }
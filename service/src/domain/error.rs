@@ -1,5 +1,8 @@
 use crate::domain::error::ErrorResponse::{
     ImageDecodeError, ImageNotFoundError, ImageNotFoundInCacheError, ImageWriteError,
+    DimensionNotAllowedError, ForbiddenSourceError, InvalidPurgeRequestError, InvalidUploadError,
+    OriginUnavailableError, PayloadTooLargeError, ImageTooLargeError, InvalidPathError, InvalidSignatureError, InvalidTransformError, UnsupportedFilterError, UploadNotSupportedError, InvalidSourceError, SourceFetchError,
+    UnauthorizedError, DuplicateParameterError, UnsupportedOptionError,
 };
 use crate::router::full;
 use http_body_util::combinators::BoxBody;
@@ -8,6 +11,7 @@ use hyper::{Response, StatusCode};
 use std::error;
 use std::fmt::{Display, Formatter};
 
+#[allow(clippy::enum_variant_names)]
 #[derive(Debug)]
 pub enum ErrorResponse
 where
@@ -17,6 +21,23 @@ where
     ImageDecodeError {},
     ImageWriteError {},
     ImageNotFoundInCacheError {},
+    UnauthorizedError {},
+    InvalidPurgeRequestError {},
+    InvalidSourceError {},
+    ForbiddenSourceError {},
+    SourceFetchError {},
+    DimensionNotAllowedError {},
+    OriginUnavailableError {},
+    InvalidUploadError {},
+    PayloadTooLargeError {},
+    UploadNotSupportedError {},
+    ImageTooLargeError {},
+    InvalidPathError {},
+    InvalidSignatureError {},
+    InvalidTransformError {},
+    UnsupportedFilterError { filter: String },
+    UnsupportedOptionError { option: String },
+    DuplicateParameterError {},
 }
 
 impl Display for ErrorResponse {
@@ -26,6 +47,23 @@ impl Display for ErrorResponse {
             ImageNotFoundInCacheError {} => write!(f, "Image not found in cache."),
             ImageDecodeError {} => write!(f, "Image could not be decoded."),
             ImageWriteError {} => write!(f, "Image could not be written."),
+            UnauthorizedError {} => write!(f, "Unauthorized."),
+            InvalidPurgeRequestError {} => write!(f, "Purge needs exactly one of path, prefix or all=true."),
+            InvalidSourceError {} => write!(f, "Image source is not a valid URL."),
+            ForbiddenSourceError {} => write!(f, "Image source is not allowed."),
+            SourceFetchError {} => write!(f, "Image source could not be fetched."),
+            DimensionNotAllowedError {} => write!(f, "Requested dimensions are not allowed."),
+            OriginUnavailableError {} => write!(f, "Image origin is unavailable."),
+            InvalidUploadError {} => write!(f, "Upload is not an accepted image."),
+            PayloadTooLargeError {} => write!(f, "Image is too large."),
+            UploadNotSupportedError {} => write!(f, "Origin does not accept uploads."),
+            ImageTooLargeError {} => write!(f, "Image dimensions exceed the limits."),
+            InvalidPathError {} => write!(f, "Image path is not valid."),
+            InvalidSignatureError {} => write!(f, "URL signature is missing, invalid or expired."),
+            InvalidTransformError {} => write!(f, "Transform is not valid."),
+            UnsupportedFilterError { filter } => write!(f, "Filter \"{filter}\" is not supported."),
+            UnsupportedOptionError { option } => write!(f, "Option \"{option}\" is not supported."),
+            DuplicateParameterError {} => write!(f, "Query parameters must not repeat."),
         }
     }
 }
@@ -35,19 +73,87 @@ impl ErrorResponse {
         match self {
             ImageNotFoundError {} => error_response(
                 StatusCode::NOT_FOUND,
-                format!("Image not found."),
+                "Image not found.".to_string(),
             ),
             ImageNotFoundInCacheError {} => error_response(
                 StatusCode::NOT_FOUND,
-                format!("Image not found."),
+                "Image not found.".to_string(),
             ),
             ImageDecodeError {} => error_response(
                 StatusCode::INTERNAL_SERVER_ERROR,
-                format!("Image could not be decoded."),
+                "Image could not be decoded.".to_string(),
             ),
             ImageWriteError {} => error_response(
                 StatusCode::INTERNAL_SERVER_ERROR,
-                format!("Image could not be written."),
+                "Image could not be written.".to_string(),
+            ),
+            UnauthorizedError {} => error_response(
+                StatusCode::UNAUTHORIZED,
+                "Unauthorized.".to_string(),
+            ),
+            InvalidPurgeRequestError {} => error_response(
+                StatusCode::BAD_REQUEST,
+                "Purge needs exactly one of path, prefix or all=true.".to_string(),
+            ),
+            InvalidSourceError {} => error_response(
+                StatusCode::BAD_REQUEST,
+                "Image source is not a valid URL.".to_string(),
+            ),
+            ForbiddenSourceError {} => error_response(
+                StatusCode::FORBIDDEN,
+                "Image source is not allowed.".to_string(),
+            ),
+            SourceFetchError {} => error_response(
+                StatusCode::BAD_GATEWAY,
+                "Image source could not be fetched.".to_string(),
+            ),
+            DimensionNotAllowedError {} => error_response(
+                StatusCode::BAD_REQUEST,
+                "Requested dimensions are not allowed.".to_string(),
+            ),
+            OriginUnavailableError {} => error_response(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Image origin is unavailable.".to_string(),
+            ),
+            InvalidUploadError {} => error_response(
+                StatusCode::BAD_REQUEST,
+                "Upload is not an accepted image.".to_string(),
+            ),
+            PayloadTooLargeError {} => error_response(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                "Image is too large.".to_string(),
+            ),
+            UploadNotSupportedError {} => error_response(
+                StatusCode::METHOD_NOT_ALLOWED,
+                "Origin does not accept uploads.".to_string(),
+            ),
+            ImageTooLargeError {} => error_response(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "Image dimensions exceed the limits.".to_string(),
+            ),
+            InvalidPathError {} => error_response(
+                StatusCode::BAD_REQUEST,
+                "Image path is not valid.".to_string(),
+            ),
+            InvalidSignatureError {} => error_response(
+                StatusCode::FORBIDDEN,
+                "URL signature is missing, invalid or expired.".to_string(),
+            ),
+            InvalidTransformError {} => error_response(
+                StatusCode::BAD_REQUEST,
+                "Transform is not valid.".to_string(),
+            ),
+            UnsupportedFilterError { filter } => error_response(
+                StatusCode::BAD_REQUEST,
+                format!("Filter \"{filter}\" is not supported."),
+            ),
+            UnsupportedOptionError { option } => error_response(
+                StatusCode::BAD_REQUEST,
+                format!("Option \"{option}\" is not supported."),
+            ),
+            DuplicateParameterError {} => error_response(
+                StatusCode::BAD_REQUEST,
+                "Query parameters must not repeat.".to_string(),
             ),
         }
     }
@@ -0,0 +1,89 @@
+use chrono::{DateTime, Utc};
+use hyper::body::Bytes;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// An original image as held by a repository, together with what the origin told us about it.
+#[derive(Debug, Clone)]
+pub struct ImageItem {
+    pub bytes: Bytes,
+    pub metadata: OriginMetadata,
+}
+
+/// Freshness information about an original, stored in a sidecar next to cached files.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct OriginMetadata {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    pub generation: Option<String>,
+    /// Unix timestamp (seconds) of the last time the origin confirmed this copy.
+    pub fetched_at: i64,
+}
+
+impl OriginMetadata {
+    pub fn new(etag: Option<String>, last_modified: Option<String>, generation: Option<String>) -> Self {
+        OriginMetadata {
+            etag,
+            last_modified,
+            generation,
+            fetched_at: Utc::now().timestamp(),
+        }
+    }
+
+    /// Whether the copy is older than `max_age` and should be revalidated.
+    pub fn is_stale(&self, max_age: Duration) -> bool {
+        Utc::now().timestamp() - self.fetched_at >= max_age.as_secs() as i64
+    }
+
+    /// Mark the copy as confirmed by the origin just now.
+    pub fn refreshed(self) -> Self {
+        OriginMetadata {
+            fetched_at: Utc::now().timestamp(),
+            ..self
+        }
+    }
+
+    pub fn last_modified_time(&self) -> Option<DateTime<Utc>> {
+        self.last_modified.as_deref().and_then(parse_http_date)
+    }
+
+    /// Whether a client holding a copy from `if_modified_since` already has the latest version.
+    pub fn not_modified_since(&self, if_modified_since: &str) -> bool {
+        match (self.last_modified_time(), parse_http_date(if_modified_since)) {
+            (Some(last_modified), Some(since)) => last_modified <= since,
+            _ => false,
+        }
+    }
+}
+
+pub fn parse_http_date(value: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc2822(value)
+        .ok()
+        .map(|date| date.with_timezone(&Utc))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn http_date_parse() {
+        let date = parse_http_date("Wed, 21 Oct 2015 07:28:00 GMT").unwrap();
+        assert_eq!(date.timestamp(), 1445412480)
+    }
+
+    #[test]
+    fn not_modified_since_compares_dates() {
+        let metadata = OriginMetadata::new(None, Some("Wed, 21 Oct 2015 07:28:00 GMT".to_string()), None);
+        assert!(metadata.not_modified_since("Wed, 21 Oct 2015 07:28:00 GMT"));
+        assert!(!metadata.not_modified_since("Tue, 20 Oct 2015 07:28:00 GMT"));
+        assert!(!metadata.not_modified_since("garbage"))
+    }
+
+    #[test]
+    fn metadata_staleness() {
+        let metadata = OriginMetadata::default();
+        assert!(metadata.is_stale(Duration::from_secs(60)));
+        assert!(!metadata.refreshed().is_stale(Duration::from_secs(60)))
+    }
+}
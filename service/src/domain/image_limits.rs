@@ -0,0 +1,58 @@
+use image::Limits;
+use serde::Deserialize;
+
+/// Bounds on the images decoded and produced, so a small file declaring huge dimensions
+/// cannot make the service allocate more than it has.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ImageLimits {
+    /// Originals whose header declares more pixels are refused before decoding.
+    pub max_input_pixels: u64,
+    /// Most memory a single decode may allocate.
+    pub max_alloc_bytes: u64,
+    pub max_output_width: u32,
+    pub max_output_height: u32,
+}
+
+impl Default for ImageLimits {
+    fn default() -> Self {
+        ImageLimits {
+            max_input_pixels: 50_000_000,
+            max_alloc_bytes: 512 * 1024 * 1024,
+            max_output_width: 8192,
+            max_output_height: 8192,
+        }
+    }
+}
+
+impl ImageLimits {
+    pub fn allows_input(&self, width: u32, height: u32) -> bool {
+        width as u64 * height as u64 <= self.max_input_pixels
+    }
+
+    pub fn allows_output(&self, width: u32, height: u32) -> bool {
+        width <= self.max_output_width && height <= self.max_output_height
+    }
+
+    /// Limits enforced by the decoders themselves, for formats whose header understates
+    /// what decoding them takes.
+    pub fn decoder_limits(&self) -> Limits {
+        let mut limits = Limits::default();
+        limits.max_alloc = Some(self.max_alloc_bytes);
+        limits
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits_bound_pixels_and_output() {
+        let limits = ImageLimits::default();
+        assert!(limits.allows_input(5000, 5000));
+        assert!(!limits.allows_input(50000, 50000));
+        assert!(limits.allows_output(8192, 100));
+        assert!(!limits.allows_output(100, 8193))
+    }
+}
@@ -0,0 +1,89 @@
+use crate::domain::error::ErrorResponse;
+use crate::domain::error::ErrorResponse::InvalidPathError;
+use percent_encoding::percent_decode_str;
+use std::borrow::Cow;
+
+/// Normalize a request path before it reaches any repository: empty and `.` segments are
+/// dropped, while `..`, encoded separators, NUL and other control characters are refused.
+/// Segments keep their original encoding, so the result is still a valid URL path.
+pub fn normalize(path: &str) -> Result<String, ErrorResponse> {
+    let segments = checked_segments(path)?;
+    Ok(format!("/{}", segments.iter().map(|(raw, _)| *raw).collect::<Vec<&str>>().join("/")))
+}
+
+/// Normalize the path of an image request, which may end in an absolute URL naming a remote
+/// original, `/t/grayscale/https://cdn.example.com/a.jpg`. The URL is kept as it is, it is
+/// checked when parsed and never reaches a repository.
+pub fn normalize_request(path: &str) -> Result<String, ErrorResponse> {
+    let url_start = ["/http://", "/https://"].iter().filter_map(|scheme| path.find(scheme)).min();
+    match url_start {
+        Some(0) => Ok(path.to_string()),
+        Some(start) => Ok(format!("{}{}", normalize(&path[..start])?.trim_end_matches('/'), &path[start..])),
+        None => normalize(path),
+    }
+}
+
+/// The percent-decoded segments of a path, for repositories mapping it onto a filesystem.
+pub fn decoded_segments(path: &str) -> Result<Vec<Cow<'_, str>>, ErrorResponse> {
+    Ok(checked_segments(path)?.into_iter().map(|(_, decoded)| decoded).collect())
+}
+
+fn checked_segments(path: &str) -> Result<Vec<(&str, Cow<'_, str>)>, ErrorResponse> {
+    if !path.starts_with('/') {
+        return Err(InvalidPathError {});
+    }
+    let mut segments = Vec::new();
+    for raw in path.split('/') {
+        let decoded = percent_decode_str(raw).decode_utf8().map_err(|_| InvalidPathError {})?;
+        match decoded.as_ref() {
+            "" | "." => continue,
+            ".." => return Err(InvalidPathError {}),
+            s if s.contains(['/', '\\']) || s.chars().any(char::is_control) => return Err(InvalidPathError {}),
+            _ => segments.push((raw, decoded)),
+        }
+    }
+    Ok(segments)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_collapses_harmless_segments() {
+        assert_eq!(normalize("/photos/a%20b.jpg").unwrap(), "/photos/a%20b.jpg");
+        assert_eq!(normalize("//photos/./%2E/a.jpg").unwrap(), "/photos/a.jpg");
+        assert_eq!(decoded_segments("/photos/a%20b.jpg").unwrap(), vec!["photos", "a b.jpg"])
+    }
+
+    #[test]
+    fn normalize_request_keeps_remote_urls() {
+        assert_eq!(normalize_request("//partner/./a.jpg").unwrap(), "/partner/a.jpg");
+        assert_eq!(
+            normalize_request("/t//grayscale/https://cdn.example.com//a.jpg").unwrap(),
+            "/t/grayscale/https://cdn.example.com//a.jpg"
+        );
+        assert_eq!(normalize_request("/https://cdn.example.com/a.jpg").unwrap(), "/https://cdn.example.com/a.jpg");
+        assert!(normalize_request("/p/../https://cdn.example.com/a.jpg").is_err())
+    }
+
+    #[test]
+    fn normalize_refuses_traversal() {
+        for path in [
+            "/../secret.jpg",
+            "/photos/%2e%2e/secret.jpg",
+            "/photos/%2E%2e/secret.jpg",
+            "/photos/.%2E/secret.jpg",
+            "/photos%2f..%2fsecret.jpg",
+            "/photos%2F%2Fetc%2Fpasswd",
+            "/photos/..%5csecret.jpg",
+            "/photos/a.jpg%00.png",
+            "/photos/a%0a.jpg",
+            "/photos/%ff.jpg",
+            "photos/a.jpg",
+            "C:\\photos\\a.jpg",
+        ] {
+            assert!(matches!(normalize(path), Err(InvalidPathError {})), "{path} was accepted");
+        }
+    }
+}
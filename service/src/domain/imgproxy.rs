@@ -0,0 +1,388 @@
+use crate::domain::dimension::Dimension;
+use crate::domain::error::ErrorResponse;
+use crate::domain::error::ErrorResponse::{
+    InvalidSignatureError, InvalidSourceError, InvalidTransformError, UnsupportedOptionError,
+};
+use crate::domain::pipeline::{Axis, Operation, Pipeline};
+use crate::domain::transform::{parse_quality, Filter, Fit, OutputFormat};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use percent_encoding::percent_decode_str;
+use serde::Deserialize;
+use sha2::Sha256;
+use tracing::{debug, error, info};
+
+/// Segment after the options introducing a source URL given as is, `plain/<url>@<format>`.
+const PLAIN_SEGMENT: &str = "plain";
+/// Signature segment of URLs built without a key.
+const INSECURE_SIGNATURE: &str = "insecure";
+/// Length of an HMAC-SHA256, the signatures told apart from path segments.
+const SIGNATURE_BYTES: usize = 32;
+/// Names and short names of the processing options of imgproxy, supported or not. A path
+/// segment is only taken for an option when named like one.
+const OPTION_NAMES: &[&str] = &[
+    "resize", "rs", "size", "s", "resizing_type", "rt", "resizing_algorithm", "ra", "width", "w", "height", "h",
+    "min-width", "mw", "min-height", "mh", "zoom", "z", "dpr", "enlarge", "el", "extend", "ex", "extend_aspect_ratio",
+    "extend_ar", "exar", "gravity", "g", "crop", "c", "crop_aspect_ratio", "car", "trim", "t", "padding", "pd",
+    "auto_rotate", "ar", "rotate", "rot", "flip", "fl", "background", "bg", "background_alpha", "bga", "adjust", "a",
+    "brightness", "br", "contrast", "co", "saturation", "sa", "blur", "bl", "sharpen", "sh", "pixelate", "pix",
+    "unsharp_masking", "ush", "blur_detections", "bd", "draw_detections", "dd", "colorize", "col", "gradient", "gr",
+    "watermark", "wm", "watermark_url", "wmu", "watermark_text", "wmt", "watermark_size", "wms", "watermark_shadow",
+    "wmsh", "style", "st", "strip_metadata", "sm", "keep_copyright", "kcr", "dpi", "strip_color_profile", "scp",
+    "enforce_thumbnail", "eth", "quality", "q", "format_quality", "fq", "autoquality", "aq", "max_bytes", "mb",
+    "jpeg_options", "jpgo", "png_options", "pngo", "webp_options", "webpo", "format", "f", "ext", "page", "pg", "pages",
+    "pgs", "disable_animation", "da", "video_thumbnail_second", "vts", "video_thumbnail_keyframes", "vtk",
+    "video_thumbnail_tile", "vtt", "fallback_image_url", "fiu", "skip_processing", "skp", "raw", "cachebuster", "cb",
+    "expires", "exp", "filename", "fn", "return_attachment", "att", "preset", "pr", "hashsum", "hs",
+    "max_src_resolution", "msr", "max_src_file_size", "msfs", "max_animation_frames", "maf",
+    "max_animation_frame_resolution", "mafr",
+];
+
+/// Serving of imgproxy URLs, `/<signature>/rs:fill:300:200/g:sm/plain/<source>@webp`, for
+/// clients built against imgproxy.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ImgproxyConfig {
+    pub enabled: bool,
+    /// Serve URLs with any signature, or `insecure`, while no key is configured, as imgproxy does.
+    pub allow_unsigned: bool,
+}
+
+/// Verifies imgproxy signatures, the unpadded URL-safe base64 HMAC-SHA256 of the salt followed
+/// by the path after the signature, with any of the configured key and salt pairs.
+#[derive(Debug, Default)]
+pub struct ImgproxySigner {
+    pairs: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+impl ImgproxySigner {
+    pub fn new(pairs: Vec<(Vec<u8>, Vec<u8>)>) -> Self {
+        ImgproxySigner { pairs }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.pairs.is_empty()
+    }
+
+    /// Hex keys and salts from comma-separated lists, paired in order as imgproxy reads them.
+    pub fn from_env_vars(keys_name: &str, salts_name: &str) -> Self {
+        let keys = std::env::var(keys_name).unwrap_or_default();
+        let salts = std::env::var(salts_name).unwrap_or_default();
+        let pairs: Vec<(Vec<u8>, Vec<u8>)> = keys
+            .split(',')
+            .zip(salts.split(','))
+            .filter(|(key, _)| !key.trim().is_empty())
+            .filter_map(|(key, salt)| match (hex::decode(key.trim()), hex::decode(salt.trim())) {
+                (Ok(key), Ok(salt)) => Some((key, salt)),
+                _ => {
+                    error!("Ignoring an imgproxy key and salt pair that is not hex.");
+                    None
+                }
+            })
+            .collect();
+        info!("imgproxy signatures checked with {} keys.", pairs.len());
+        ImgproxySigner::new(pairs)
+    }
+
+    /// Check the signature of `rest`, the path after the signature including its leading slash.
+    pub fn verify(&self, config: &ImgproxyConfig, signature: &str, rest: &str) -> Result<(), ErrorResponse> {
+        if !self.is_enabled() {
+            return match config.allow_unsigned {
+                true => Ok(()),
+                false => Err(InvalidSignatureError {}),
+            };
+        }
+        let signature = URL_SAFE_NO_PAD.decode(signature).map_err(|_| InvalidSignatureError {})?;
+        let valid = self.pairs.iter().any(|(key, salt)| {
+            let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+            mac.update(salt);
+            mac.update(rest.as_bytes());
+            mac.verify_slice(&signature).is_ok()
+        });
+        if !valid {
+            debug!("Invalid imgproxy signature for {rest}");
+            return Err(InvalidSignatureError {});
+        }
+        Ok(())
+    }
+}
+
+/// Split an imgproxy URL into its signature and the signed rest, with its leading slash.
+/// Only paths shaped like one are taken for one: a signature or `insecure`, any processing
+/// options, then `plain/<url>` or a base64 source decoding to a URL served.
+pub fn split_imgproxy_path(path: &str) -> Option<(&str, &str)> {
+    let (signature, rest) = path.strip_prefix('/')?.split_once('/')?;
+    if !is_signature(signature) {
+        return None;
+    }
+    let mut segments = rest.split('/').skip_while(|segment| is_option(segment));
+    let source = match segments.next()? {
+        PLAIN_SEGMENT => segments.next().is_some_and(|url| !url.is_empty()),
+        encoded => encoded_source(&std::iter::once(encoded).chain(segments).collect::<String>())
+            .is_ok_and(|(url, _)| image_path(&url).is_ok()),
+    };
+    source.then(|| (signature, &path[signature.len() + 1..]))
+}
+
+/// Whether a path segment is a signature, `insecure` or the unpadded base64 of an HMAC-SHA256.
+fn is_signature(segment: &str) -> bool {
+    segment == INSECURE_SIGNATURE
+        || URL_SAFE_NO_PAD.decode(segment).is_ok_and(|signature| signature.len() == SIGNATURE_BYTES)
+}
+
+/// Whether a path segment is a processing option, `rs:fill:300:200` but not `2024:09`.
+fn is_option(segment: &str) -> bool {
+    segment.split_once(':').is_some_and(|(name, _)| OPTION_NAMES.contains(&name))
+}
+
+/// Parse the part of an imgproxy URL after the signature, `/<option>/.../plain/<url>@<format>`
+/// or `/<option>/.../<base64 url>.<format>`, into a pipeline and the image path. Rotation and
+/// flips come first as the size is that of the rotated image, crops by gravity stay centered.
+pub fn parse_imgproxy(rest: &str) -> Result<(Pipeline, String), ErrorResponse> {
+    let mut segments = rest.strip_prefix('/').unwrap_or(rest).split('/');
+    let mut options = ImgproxyOptions::default();
+    let source = loop {
+        match segments.next() {
+            Some(PLAIN_SEGMENT) => break plain_source(&segments.collect::<Vec<&str>>().join("/"))?,
+            Some(option) if is_option(option) => options.apply(option)?,
+            Some(encoded) => break encoded_source(&std::iter::once(encoded).chain(segments).collect::<String>())?,
+            None => return Err(InvalidSourceError {}),
+        }
+    };
+    let (url, extension) = source;
+    if let Some(extension) = extension {
+        options.operations.push(Operation::Format(extension.parse()?));
+    }
+    Ok((options.into_pipeline(), image_path(&url)?))
+}
+
+/// `<url>@<format>`, percent-encoded when the URL has characters of its own to escape. An `@`
+/// not followed by a known format is part of the URL.
+fn plain_source(source: &str) -> Result<(String, Option<String>), ErrorResponse> {
+    let (url, extension) = match source.rsplit_once('@') {
+        Some((url, extension)) if extension.parse::<OutputFormat>().is_ok() => (url, Some(extension.to_string())),
+        _ => (source, None),
+    };
+    let url = percent_decode_str(url).decode_utf8().map_err(|_| InvalidSourceError {})?;
+    Ok((url.into_owned(), extension))
+}
+
+/// `<base64 url>.<format>`, the base64 may be split by slashes.
+fn encoded_source(source: &str) -> Result<(String, Option<String>), ErrorResponse> {
+    let (encoded, extension) = match source.rsplit_once('.') {
+        Some((encoded, extension)) => (encoded, Some(extension.to_string())),
+        None => (source, None),
+    };
+    let url = URL_SAFE_NO_PAD
+        .decode(encoded.trim_end_matches('='))
+        .ok()
+        .and_then(|url| String::from_utf8(url).ok())
+        .ok_or(InvalidSourceError {})?;
+    Ok((url, extension))
+}
+
+/// The path a source URL is served from, `local:///a.jpg` from the repository and absolute
+/// URLs as remote images.
+fn image_path(url: &str) -> Result<String, ErrorResponse> {
+    match url.strip_prefix("local://") {
+        Some(path) if path.starts_with('/') => Ok(path.to_string()),
+        Some(_) => Err(InvalidSourceError {}),
+        None if url.starts_with("http://") || url.starts_with("https://") => Ok(format!("/{url}")),
+        None => Err(InvalidSourceError {}),
+    }
+}
+
+/// Processing options as they accumulate, a later option overriding an earlier one as in imgproxy.
+#[derive(Debug, Default)]
+struct ImgproxyOptions {
+    width: u32,
+    height: u32,
+    fit: Fit,
+    rotation: Option<Operation>,
+    flips: Vec<Operation>,
+    operations: Vec<Operation>,
+}
+
+impl ImgproxyOptions {
+    fn apply(&mut self, option: &str) -> Result<(), ErrorResponse> {
+        let mut arguments = option.split(':');
+        let name = arguments.next().unwrap_or_default();
+        let arguments: Vec<&str> = arguments.collect();
+        let side = |side: &str| side.parse::<u32>().map_err(|_| InvalidTransformError {});
+        let sigma = |filter: &str, sigma: &str| format!("{filter}:{sigma}").parse::<Filter>();
+        match (name, &arguments[..]) {
+            ("resize" | "rs", [resizing_type, rest @ ..]) => {
+                self.fit = resizing_fit(resizing_type)?;
+                self.apply_size(rest)?;
+            }
+            ("size" | "s", sizes) => self.apply_size(sizes)?,
+            ("resizing_type" | "rt", [resizing_type]) => self.fit = resizing_fit(resizing_type)?,
+            ("width" | "w", [width]) => self.width = side(width)?,
+            ("height" | "h", [height]) => self.height = side(height)?,
+            // Nothing is cropped away but around the center.
+            ("gravity" | "g", [_, ..]) => {}
+            ("quality" | "q", ["0"]) => {}
+            ("quality" | "q", [quality]) => self.operations.push(Operation::Quality(parse_quality(quality)?)),
+            ("format" | "f" | "ext", [format]) => self.operations.push(Operation::Format(format.parse()?)),
+            ("blur" | "bl", ["0"]) | ("sharpen" | "sh", ["0"]) => {}
+            ("blur" | "bl", [value]) => self.operations.push(Operation::Filter(sigma("blur", value)?)),
+            ("sharpen" | "sh", [value]) => self.operations.push(Operation::Filter(sigma("sharpen", value)?)),
+            ("rotate" | "rot", ["0"]) => self.rotation = None,
+            ("rotate" | "rot", [degrees @ ("90" | "180" | "270")]) => {
+                self.rotation = Some(Operation::Rotate(degrees.parse().unwrap_or_default()))
+            }
+            ("flip" | "fl", [horizontal, rest @ ..]) => {
+                let vertical = rest.first().copied().unwrap_or("0");
+                self.flips = [(horizontal, Axis::Horizontal), (&vertical, Axis::Vertical)]
+                    .into_iter()
+                    .filter_map(|(flip, axis)| parse_bool(flip).map(|flip| flip.then_some(Operation::Flip(axis))).transpose())
+                    .collect::<Result<Vec<Operation>, _>>()?;
+            }
+            // Nothing is carried over from the original but its pixels.
+            ("strip_metadata" | "sm" | "strip_color_profile" | "scp", [_]) => {}
+            (
+                "resize" | "rs" | "resizing_type" | "rt" | "width" | "w" | "height" | "h" | "gravity" | "g" | "quality"
+                | "q" | "format" | "f" | "ext" | "blur" | "bl" | "sharpen" | "sh" | "rotate" | "rot" | "flip" | "fl"
+                | "strip_metadata" | "sm" | "strip_color_profile" | "scp",
+                _,
+            ) => return Err(InvalidTransformError {}),
+            (name, _) => {
+                debug!("Unsupported imgproxy option {name}");
+                return Err(UnsupportedOptionError { option: name.to_string() });
+            }
+        }
+        Ok(())
+    }
+
+    /// `%width:%height:%enlarge:%extend`, every part optional. Images are always enlarged and
+    /// never extended.
+    fn apply_size(&mut self, sizes: &[&str]) -> Result<(), ErrorResponse> {
+        let side = |index: usize| match sizes.get(index).copied() {
+            None | Some("") => Ok(None),
+            Some(side) => side.parse::<u32>().map(Some).map_err(|_| InvalidTransformError {}),
+        };
+        if let Some(width) = side(0)? {
+            self.width = width;
+        }
+        if let Some(height) = side(1)? {
+            self.height = height;
+        }
+        match sizes.len() {
+            0..=4 => Ok(()),
+            _ => Err(InvalidTransformError {}),
+        }
+    }
+
+    fn into_pipeline(self) -> Pipeline {
+        let dimension = match (self.width, self.height) {
+            (0, 0) => None,
+            (width, 0) => Some(Dimension::Width(width)),
+            (0, height) => Some(Dimension::Height(height)),
+            (width, height) => Some(Dimension::Bounds { width, height }),
+        };
+        let resize = dimension.map(|dimension| Operation::Resize { dimension, fit: self.fit });
+        let operations = self
+            .rotation
+            .into_iter()
+            .chain(self.flips)
+            .chain(resize)
+            .chain(self.operations)
+            .collect();
+        Pipeline { operations }
+    }
+}
+
+fn resizing_fit(resizing_type: &str) -> Result<Fit, ErrorResponse> {
+    match resizing_type {
+        "fit" | "" => Ok(Fit::Contain),
+        "fill" | "fill-down" => Ok(Fit::Cover),
+        "force" => Ok(Fit::Fill),
+        _ => Err(InvalidTransformError {}),
+    }
+}
+
+fn parse_bool(value: &str) -> Result<bool, ErrorResponse> {
+    match value {
+        "1" | "t" | "true" => Ok(true),
+        "0" | "f" | "false" | "" => Ok(false),
+        _ => Err(InvalidTransformError {}),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn imgproxy_urls_parse_into_pipelines() {
+        let path = "/insecure/rs:fill:300:200/g:sm/plain/local:///photos/a.jpg@webp";
+        let (signature, rest) = split_imgproxy_path(path).unwrap();
+        assert_eq!(signature, "insecure");
+        let (pipeline, image) = parse_imgproxy(rest).unwrap();
+        assert_eq!(image, "/photos/a.jpg");
+        assert_eq!(
+            pipeline.operations,
+            vec![
+                Operation::Resize { dimension: Dimension::Bounds { width: 300, height: 200 }, fit: Fit::Cover },
+                Operation::Format(OutputFormat::Webp),
+            ]
+        );
+
+        let encoded = URL_SAFE_NO_PAD.encode("https://cdn.example.com/a.jpg");
+        let (pipeline, image) = parse_imgproxy(&format!("/w:400/rot:90/fl:1/bl:2/q:80/{encoded}.png")).unwrap();
+        assert_eq!(image, "/https://cdn.example.com/a.jpg");
+        assert_eq!(
+            pipeline.operations,
+            vec![
+                Operation::Rotate(90),
+                Operation::Flip(Axis::Horizontal),
+                Operation::Resize { dimension: Dimension::Width(400), fit: Fit::Contain },
+                Operation::Filter(Filter::Blur(2.0)),
+                Operation::Quality(80),
+                Operation::Format(OutputFormat::Png),
+            ]
+        );
+        let signature = URL_SAFE_NO_PAD.encode([0; SIGNATURE_BYTES]);
+        assert!(split_imgproxy_path(&format!("/{signature}/{encoded}.png")).is_some());
+
+        let (pipeline, image) = parse_imgproxy("/plain/https://cdn.example.com/@team/a.jpg").unwrap();
+        assert_eq!(image, "/https://cdn.example.com/@team/a.jpg");
+        assert!(pipeline.operations.is_empty())
+    }
+
+    #[test]
+    fn imgproxy_urls_are_told_from_other_paths() {
+        for path in ["/photos/a.jpg", "/photos/2024:09/a.jpg", "/photos/w:400.jpg", "/x/q:1/a.png", "/insecure/w:400/a.jpg"] {
+            assert_eq!(split_imgproxy_path(path), None, "{path} was taken for an imgproxy URL");
+        }
+    }
+
+    #[test]
+    fn imgproxy_refuses_unsupported_options() {
+        match parse_imgproxy("/wm:0.5/plain/local:///a.jpg") {
+            Err(UnsupportedOptionError { option }) => assert_eq!(option, "wm"),
+            other => panic!("wm was not refused: {other:?}"),
+        }
+        for rest in ["/rs:auto:10:10/plain/local:///a.jpg", "/rot:45/plain/local:///a.jpg", "/w:10/plain/ftp://a/b.jpg"] {
+            assert!(parse_imgproxy(rest).is_err(), "{rest} was accepted");
+        }
+    }
+
+    #[test]
+    fn imgproxy_signatures_verify() {
+        let config = ImgproxyConfig { enabled: true, allow_unsigned: false };
+        let signer = ImgproxySigner::new(vec![(b"key".to_vec(), b"salt".to_vec())]);
+        let rest = "/rs:fill:300:200/plain/local:///a.jpg";
+        let mut mac = Hmac::<Sha256>::new_from_slice(b"key").unwrap();
+        mac.update(b"salt");
+        mac.update(rest.as_bytes());
+        let signature = URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes());
+
+        assert!(signer.verify(&config, &signature, rest).is_ok());
+        assert!(signer.verify(&config, &signature, "/rs:fill:301:200/plain/local:///a.jpg").is_err());
+        assert!(ImgproxySigner::default().verify(&config, "insecure", rest).is_err());
+        let allow_unsigned = ImgproxyConfig { allow_unsigned: true, ..config };
+        assert!(ImgproxySigner::default().verify(&allow_unsigned, "insecure", rest).is_ok())
+    }
+}
@@ -4,9 +4,25 @@ use hyper::body::Bytes;
 use image::ImageFormat;
 use tracing::warn;
 
+pub mod cache_policy;
 pub mod dimension;
 pub mod error;
+pub mod image_item;
+pub mod image_limits;
+pub mod image_path;
+pub mod imgproxy;
+pub mod pipeline;
+pub mod preset;
+pub mod purge;
+pub mod query;
 pub mod server_timing;
+pub mod source;
+pub mod status;
+pub mod thumbor;
+pub mod transform;
+pub mod transform_policy;
+pub mod upload;
+pub mod url_signature;
 
 #[derive(Debug)]
 pub struct ImageData {
@@ -14,6 +30,16 @@ pub struct ImageData {
     pub server_timing: ServerTiming,
     pub format_extension: String,
     pub content_length: u64,
+    pub last_modified: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum ImageResponse {
+    Image(ImageData),
+    /// The client's copy is current, answered with a 304.
+    NotModified { last_modified: String },
+    /// The request is served under another URL, answered with a 302.
+    Redirect { location: String },
 }
 
 pub fn format_from_path(path: &str) -> ImageFormat {
@@ -0,0 +1,234 @@
+use crate::domain::dimension::Dimension;
+use crate::domain::error::ErrorResponse;
+use crate::domain::error::ErrorResponse::InvalidTransformError;
+use crate::domain::transform::{parse_quality, Filter, Fit, OutputFormat, Transform};
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+/// Path prefix of the `/t/<operation>/.../<path>` form.
+pub const PIPELINE_PATH_PREFIX: &str = "/t/";
+/// More operations than this are refused, each one costs a pass over the pixels.
+const MAX_OPERATIONS: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Axis {
+    Horizontal,
+    Vertical,
+}
+
+/// A single step of a pipeline, written `name:arguments` in the path.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operation {
+    /// `crop:x,y,width,height`, in pixels of the image at that step.
+    Crop { x: u32, y: u32, width: u32, height: u32 },
+    /// `resize:w=400`, `resize:h=300` or `resize:w=400,h=300,fit=cover`.
+    Resize { dimension: Dimension, fit: Fit },
+    /// `rotate:90`, clockwise by 90, 180 or 270 degrees.
+    Rotate(u16),
+    /// `flip:h` or `flip:v`.
+    Flip(Axis),
+    /// `blur:<sigma>`, `sharpen:<sigma>` or `grayscale`.
+    Filter(Filter),
+    /// `format:webp`, the format the result is encoded as.
+    Format(OutputFormat),
+    /// `quality:80`, the JPEG quality the result is encoded with.
+    Quality(u8),
+}
+
+impl Operation {
+    fn is_named(name: &str) -> bool {
+        matches!(
+            name,
+            "crop" | "resize" | "rotate" | "flip" | "blur" | "sharpen" | "grayscale" | "format" | "quality"
+        )
+    }
+}
+
+impl FromStr for Operation {
+    type Err = ErrorResponse;
+
+    fn from_str(operation: &str) -> Result<Self, Self::Err> {
+        let (name, arguments) = operation.split_once(':').unwrap_or((operation, ""));
+        match name {
+            "crop" => {
+                let numbers = arguments
+                    .split(',')
+                    .map(|n| n.parse::<u32>().map_err(|_| InvalidTransformError {}))
+                    .collect::<Result<Vec<u32>, _>>()?;
+                match numbers[..] {
+                    [x, y, width, height] if width > 0 && height > 0 => Ok(Operation::Crop { x, y, width, height }),
+                    _ => Err(InvalidTransformError {}),
+                }
+            }
+            "resize" => parse_resize(arguments),
+            "rotate" => match arguments {
+                "90" | "180" | "270" => Ok(Operation::Rotate(arguments.parse().unwrap_or_default())),
+                _ => Err(InvalidTransformError {}),
+            },
+            "flip" => match arguments {
+                "h" => Ok(Operation::Flip(Axis::Horizontal)),
+                "v" => Ok(Operation::Flip(Axis::Vertical)),
+                _ => Err(InvalidTransformError {}),
+            },
+            "format" => Ok(Operation::Format(arguments.parse()?)),
+            "quality" => Ok(Operation::Quality(parse_quality(arguments)?)),
+            _ => Ok(Operation::Filter(operation.parse()?)),
+        }
+    }
+}
+
+fn parse_resize(arguments: &str) -> Result<Operation, ErrorResponse> {
+    let (mut width, mut height, mut fit) = (None, None, Fit::default());
+    for argument in arguments.split(',') {
+        let side = |value: &str| match value.parse::<u32>() {
+            Ok(side) if side > 0 => Ok(Some(side)),
+            _ => Err(InvalidTransformError {}),
+        };
+        match argument.split_once('=') {
+            Some(("w", value)) => width = side(value)?,
+            Some(("h", value)) => height = side(value)?,
+            Some(("fit", value)) => fit = value.parse()?,
+            _ => return Err(InvalidTransformError {}),
+        }
+    }
+    let dimension = match (width, height) {
+        (Some(width), Some(height)) => Dimension::Bounds { width, height },
+        (Some(width), None) => Dimension::Width(width),
+        (None, Some(height)) => Dimension::Height(height),
+        (None, None) => return Err(InvalidTransformError {}),
+    };
+    Ok(Operation::Resize { dimension, fit })
+}
+
+impl Display for Operation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Operation::Crop { x, y, width, height } => write!(f, "crop:{x},{y},{width},{height}"),
+            Operation::Resize { dimension, fit } => match dimension {
+                Dimension::Width(width) => write!(f, "resize:w={width},fit={fit}"),
+                Dimension::Height(height) => write!(f, "resize:h={height},fit={fit}"),
+                Dimension::Bounds { width, height } => write!(f, "resize:w={width},h={height},fit={fit}"),
+            },
+            Operation::Rotate(degrees) => write!(f, "rotate:{degrees}"),
+            Operation::Flip(Axis::Horizontal) => write!(f, "flip:h"),
+            Operation::Flip(Axis::Vertical) => write!(f, "flip:v"),
+            Operation::Filter(filter) => write!(f, "{filter}"),
+            Operation::Format(format) => write!(f, "format:{format}"),
+            Operation::Quality(quality) => write!(f, "quality:{quality}"),
+        }
+    }
+}
+
+/// Operations run in order on the decoded original, see `image_service::run_pipeline`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Pipeline {
+    pub operations: Vec<Operation>,
+}
+
+impl Pipeline {
+    /// Parse the path following `/t`, `/crop:0,0,800,600/resize:w=400/<path>`, into the
+    /// pipeline and the path of the image. Segments are operations for as long as they are
+    /// named like one, an operation with invalid arguments is refused rather than taken for
+    /// part of the path.
+    pub fn parse_path(path: &str) -> Result<(Pipeline, &str), ErrorResponse> {
+        let mut operations = Vec::new();
+        let mut rest = path;
+        while let Some(segment) = rest.strip_prefix('/').and_then(|r| r.split('/').next()) {
+            let name = segment.split(':').next().unwrap_or_default();
+            if !Operation::is_named(name) {
+                if segment.contains(':') && !matches!(name, "http" | "https") {
+                    return Err(InvalidTransformError {});
+                }
+                break;
+            }
+            operations.push(segment.parse()?);
+            rest = &rest[segment.len() + 1..];
+        }
+        if operations.len() > MAX_OPERATIONS || rest.len() <= 1 {
+            return Err(InvalidTransformError {});
+        }
+        Ok((Pipeline { operations }, rest))
+    }
+
+    /// The format set by the last `format` operation.
+    pub fn format(&self) -> Option<OutputFormat> {
+        self.operations.iter().rev().find_map(|operation| match operation {
+            Operation::Format(format) => Some(*format),
+            _ => None,
+        })
+    }
+
+    /// The quality set by the last `quality` operation.
+    pub fn quality(&self) -> Option<u8> {
+        self.operations.iter().rev().find_map(|operation| match operation {
+            Operation::Quality(quality) => Some(*quality),
+            _ => None,
+        })
+    }
+}
+
+impl From<Transform> for Pipeline {
+    /// The query model as a pipeline, resizing before filtering.
+    fn from(transform: Transform) -> Self {
+        let resize = transform.dimension.map(|dimension| Operation::Resize {
+            dimension,
+            fit: transform.fit,
+        });
+        let operations = resize
+            .into_iter()
+            .chain(transform.filters.into_iter().map(Operation::Filter))
+            .chain(transform.format.map(Operation::Format))
+            .chain(transform.quality.map(Operation::Quality))
+            .collect();
+        Pipeline { operations }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pipeline_parses_operations_then_path() {
+        let (pipeline, path) = Pipeline::parse_path("/crop:0,0,800,600/resize:w=400/sharpen:0.5/format:webp/a/b.jpg").unwrap();
+        assert_eq!(path, "/a/b.jpg");
+        assert_eq!(
+            pipeline.operations,
+            vec![
+                Operation::Crop { x: 0, y: 0, width: 800, height: 600 },
+                Operation::Resize { dimension: Dimension::Width(400), fit: Fit::Contain },
+                Operation::Filter(Filter::Sharpen(0.5)),
+                Operation::Format(OutputFormat::Webp),
+            ]
+        );
+        assert_eq!(pipeline.format(), Some(OutputFormat::Webp));
+
+        let (pipeline, path) = Pipeline::parse_path("/grayscale/https://cdn.example.com/a.jpg").unwrap();
+        assert_eq!((pipeline.operations.len(), path), (1, "/https://cdn.example.com/a.jpg"))
+    }
+
+    #[test]
+    fn pipeline_refuses_invalid_operations() {
+        for path in [
+            "/crop:0,0,800/a.jpg",
+            "/resize:w=0/a.jpg",
+            "/resize:x=10/a.jpg",
+            "/rotate:45/a.jpg",
+            "/sepia:1/a.jpg",
+            "/quality:0/a.jpg",
+            "/resize:w=10",
+            "/grayscale/",
+        ] {
+            assert!(matches!(Pipeline::parse_path(path), Err(InvalidTransformError {})), "{path} was accepted");
+        }
+        let too_long = format!("{}/a.jpg", "/grayscale".repeat(MAX_OPERATIONS + 1));
+        assert!(Pipeline::parse_path(&too_long).is_err())
+    }
+
+    #[test]
+    fn operations_display_as_parsed() {
+        for operation in ["crop:1,2,3,4", "resize:w=10,h=20,fit=cover", "rotate:270", "flip:v", "blur:1.5", "quality:80"] {
+            assert_eq!(operation.parse::<Operation>().unwrap().to_string(), operation);
+        }
+    }
+}
@@ -0,0 +1,142 @@
+use crate::domain::error::ErrorResponse;
+use crate::domain::error::ErrorResponse::InvalidTransformError;
+use crate::domain::query::QueryParams;
+use crate::domain::transform::{Filter, Fit, OutputFormat};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Query parameter naming a preset, the `/p/<name>/<path>` form does the same.
+pub const PRESET_PARAM: &str = "preset";
+/// Path prefix of the `/p/<name>/<path>` form.
+pub const PRESET_PATH_PREFIX: &str = "/p/";
+
+/// A named transform from config, requested as `?preset=<name>` or `/p/<name>/<path>`.
+/// Any parameter given in the request overrides that of the preset.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "PresetFields")]
+pub struct Preset {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub width: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub height: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fit: Option<Fit>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub format: Option<OutputFormat>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quality: Option<u8>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub filters: Vec<Filter>,
+}
+
+/// A preset as written in config, checked before the service starts serving it.
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct PresetFields {
+    width: Option<u32>,
+    height: Option<u32>,
+    fit: Option<Fit>,
+    format: Option<OutputFormat>,
+    quality: Option<u8>,
+    filters: Vec<Filter>,
+}
+
+impl TryFrom<PresetFields> for Preset {
+    type Error = String;
+
+    fn try_from(fields: PresetFields) -> Result<Self, Self::Error> {
+        if let Some(quality) = fields.quality.filter(|quality| !(1..=100).contains(quality)) {
+            return Err(format!("preset quality must be from 1 to 100, not {quality}"));
+        }
+        if fields.width.is_some() && fields.height.is_some() && fields.fit.is_none() {
+            return Err("preset with both width and height needs a fit".to_string());
+        }
+        Ok(Preset {
+            width: fields.width,
+            height: fields.height,
+            fit: fields.fit,
+            format: fields.format,
+            quality: fields.quality,
+            filters: fields.filters,
+        })
+    }
+}
+
+impl Preset {
+    /// The preset as the query parameters it stands for.
+    fn params(&self) -> Vec<(&'static str, String)> {
+        let filters = (!self.filters.is_empty()).then(|| {
+            self.filters.iter().map(Filter::to_string).collect::<Vec<String>>().join(",")
+        });
+        [
+            ("width", self.width.map(|width| width.to_string())),
+            ("height", self.height.map(|height| height.to_string())),
+            ("fit", self.fit.map(|fit| fit.to_string())),
+            ("format", self.format.map(|format| format.to_string())),
+            ("quality", self.quality.map(|quality| quality.to_string())),
+            ("filters", filters),
+        ]
+        .into_iter()
+        .filter_map(|(name, value)| value.map(|value| (name, value)))
+        .collect()
+    }
+}
+
+/// Split `/p/<name>/<path>` into the preset name and the image path.
+pub fn split_preset_path(path: &str) -> Option<(&str, &str)> {
+    let rest = path.strip_prefix(PRESET_PATH_PREFIX)?;
+    let slash = rest.find('/')?;
+    Some((&rest[..slash], &rest[slash..]))
+}
+
+/// The parameters a request stands for once its preset, from the path or the `preset`
+/// parameter, is expanded. Parameters of the request override those of the preset.
+pub fn expand_preset(
+    presets: &BTreeMap<String, Preset>,
+    path_preset: Option<&str>,
+    params: &QueryParams,
+) -> Result<QueryParams, ErrorResponse> {
+    let mut expanded = params.clone();
+    let Some(name) = path_preset.or(params.get(PRESET_PARAM).map(String::as_str)) else {
+        return Ok(expanded);
+    };
+    let preset = presets.get(name).ok_or(InvalidTransformError {})?;
+    for (name, value) in preset.params() {
+        expanded.entry(name.to_string()).or_insert(value);
+    }
+    Ok(expanded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::query::{encode_query, query_params};
+
+    fn presets() -> BTreeMap<String, Preset> {
+        let thumb = Preset {
+            width: Some(160),
+            height: Some(160),
+            fit: Some(Fit::Cover),
+            filters: vec![Filter::Sharpen(0.5)],
+            ..Preset::default()
+        };
+        BTreeMap::from([("thumb".to_string(), thumb)])
+    }
+
+    #[test]
+    fn presets_expand_under_overrides() {
+        let expanded = expand_preset(&presets(), Some("thumb"), &query_params("width=320")).unwrap();
+        assert_eq!(encode_query(&expanded), "filters=sharpen:0.5&fit=cover&height=160&width=320");
+        let expanded = expand_preset(&presets(), None, &query_params("preset=thumb")).unwrap();
+        assert_eq!(encode_query(&expanded), "filters=sharpen:0.5&fit=cover&height=160&preset=thumb&width=160");
+        assert_eq!(expand_preset(&presets(), None, &query_params("width=5")).unwrap(), query_params("width=5"));
+        assert!(matches!(expand_preset(&presets(), Some("huge"), &QueryParams::new()), Err(InvalidTransformError {})))
+    }
+
+    #[test]
+    fn preset_paths_split() {
+        assert_eq!(split_preset_path("/p/thumb/a/b.jpg"), Some(("thumb", "/a/b.jpg")));
+        assert_eq!(split_preset_path("/p/thumb"), None);
+        assert_eq!(split_preset_path("/photos/a.jpg"), None)
+    }
+}
@@ -0,0 +1,157 @@
+use crate::domain::error::ErrorResponse;
+use crate::domain::error::ErrorResponse::InvalidPurgeRequestError;
+use crate::domain::image_path::normalize;
+use crate::domain::query::query_params;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::ops::AddAssign;
+
+/// Which cached entries a purge removes, keyed on the request path of the original.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PurgeScope {
+    Path(String),
+    Prefix(String),
+    All,
+}
+
+impl PurgeScope {
+    /// Build a scope from exactly one of `path=`, `prefix=` or `all=true`.
+    pub fn from_query(opt_query: Option<&str>) -> Result<PurgeScope, ErrorResponse> {
+        let params = opt_query.map(query_params).unwrap_or_default();
+        let scope = match (
+            params.get("path"),
+            params.get("prefix"),
+            params.get("all").map(String::as_str),
+        ) {
+            (Some(path), None, None) => PurgeScope::Path(normalized(path)?),
+            (None, Some(prefix), None) => PurgeScope::Prefix(normalized(prefix)?),
+            (None, None, Some("true")) => PurgeScope::All,
+            _ => return Err(InvalidPurgeRequestError {}),
+        };
+        Ok(scope)
+    }
+
+    pub fn matches(&self, key: &str) -> bool {
+        match self {
+            PurgeScope::Path(path) => key == path,
+            PurgeScope::Prefix(prefix) => key.starts_with(prefix.as_str()),
+            PurgeScope::All => true,
+        }
+    }
+
+    /// The part of the scope under a mount at `prefix`, in the mount's own paths.
+    /// `prefix` ends with a `/`, which is kept as the leading `/` of mount paths.
+    pub fn within(&self, prefix: &str) -> Option<PurgeScope> {
+        let relative = |path: &str| path.strip_prefix(prefix).map(|rest| format!("/{rest}"));
+        match self {
+            PurgeScope::Path(path) => relative(path).map(PurgeScope::Path),
+            PurgeScope::Prefix(scope_prefix) => match relative(scope_prefix) {
+                Some(mount_prefix) => Some(PurgeScope::Prefix(mount_prefix)),
+                None if prefix.starts_with(scope_prefix.as_str()) => Some(PurgeScope::All),
+                None => None,
+            },
+            PurgeScope::All => Some(PurgeScope::All),
+        }
+    }
+}
+
+/// `path` as cache keys are stored, see `normalize`. A trailing slash is kept, so a prefix
+/// still stops at a directory.
+fn normalized(path: &str) -> Result<String, ErrorResponse> {
+    let mut normalized = normalize(path).map_err(|_| InvalidPurgeRequestError {})?;
+    if path.ends_with('/') && !normalized.ends_with('/') {
+        normalized.push('/');
+    }
+    Ok(normalized)
+}
+
+/// What a single cache removed.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize)]
+pub struct PurgeReport {
+    pub entries: u64,
+    pub bytes: u64,
+}
+
+impl AddAssign for PurgeReport {
+    fn add_assign(&mut self, other: Self) {
+        self.entries += other.entries;
+        self.bytes += other.bytes;
+    }
+}
+
+/// Totals of a purge across every cache, with the per-cache breakdown.
+#[derive(Debug, Default, Serialize)]
+pub struct PurgeSummary {
+    pub entries: u64,
+    pub bytes: u64,
+    pub caches: BTreeMap<&'static str, PurgeReport>,
+}
+
+impl PurgeSummary {
+    pub fn record(&mut self, cache: &'static str, report: PurgeReport) {
+        self.entries += report.entries;
+        self.bytes += report.bytes;
+        *self.caches.entry(cache).or_default() += report;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn purge_scope_within_mount() {
+        let path = PurgeScope::Path("/shop/a.jpg".to_string());
+        assert_eq!(path.within("/shop/"), Some(PurgeScope::Path("/a.jpg".to_string())));
+        assert_eq!(path.within("/portfolio/"), None);
+        let prefix = PurgeScope::Prefix("/shop/2024/".to_string());
+        assert_eq!(prefix.within("/shop/"), Some(PurgeScope::Prefix("/2024/".to_string())));
+        assert_eq!(PurgeScope::Prefix("/".to_string()).within("/shop/"), Some(PurgeScope::All))
+    }
+
+    #[test]
+    fn purge_scope_from_query() {
+        assert_eq!(
+            PurgeScope::from_query(Some("path=/a/b.jpg")).unwrap(),
+            PurgeScope::Path("/a/b.jpg".to_string())
+        );
+        assert_eq!(
+            PurgeScope::from_query(Some("prefix=%2Fa%2F")).unwrap(),
+            PurgeScope::Prefix("/a/".to_string())
+        );
+        assert_eq!(PurgeScope::from_query(Some("all=true")).unwrap(), PurgeScope::All);
+        assert!(PurgeScope::from_query(None).is_err());
+        assert!(PurgeScope::from_query(Some("path=/a.jpg&all=true")).is_err());
+        assert!(PurgeScope::from_query(Some("path=a.jpg")).is_err())
+    }
+
+    #[test]
+    fn purge_scope_is_normalized_like_cache_keys() {
+        assert_eq!(
+            PurgeScope::from_query(Some("path=/a//b.jpg")).unwrap(),
+            PurgeScope::Path("/a/b.jpg".to_string())
+        );
+        assert_eq!(
+            PurgeScope::from_query(Some("prefix=/a/./")).unwrap(),
+            PurgeScope::Prefix("/a/".to_string())
+        );
+        assert!(matches!(PurgeScope::from_query(Some("path=/a/../b.jpg")), Err(InvalidPurgeRequestError {})))
+    }
+
+    #[test]
+    fn purge_scope_matches() {
+        assert!(PurgeScope::Prefix("/a/".to_string()).matches("/a/b.jpg"));
+        assert!(!PurgeScope::Path("/a/b.jpg".to_string()).matches("/a/b.jpg.png"));
+        assert!(PurgeScope::All.matches("/anything"))
+    }
+
+    #[test]
+    fn purge_summary_totals() {
+        let mut summary = PurgeSummary::default();
+        summary.record("volume", PurgeReport { entries: 2, bytes: 10 });
+        summary.record("volume", PurgeReport { entries: 1, bytes: 5 });
+        assert_eq!(summary.entries, 3);
+        assert_eq!(summary.bytes, 15);
+        assert_eq!(summary.caches["volume"], PurgeReport { entries: 3, bytes: 15 })
+    }
+}
@@ -0,0 +1,79 @@
+use crate::domain::error::ErrorResponse;
+use crate::domain::error::ErrorResponse::DuplicateParameterError;
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use std::collections::HashMap;
+
+/// Characters left as they are in query values built here, `filters=sharpen:0.5,grayscale`.
+const QUERY_VALUE: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~')
+    .remove(b':')
+    .remove(b',');
+
+/// Percent-decoded query parameters of an image request, read by the signature check and
+/// every part of the transform alike.
+pub type QueryParams = HashMap<String, String>;
+
+/// Split a query string into percent-decoded key/value pairs, pairs without a value are dropped.
+pub fn query_params(query: &str) -> HashMap<String, String> {
+    query
+        .split('&')
+        .filter_map(|pair| {
+            let (key, value) = pair.split_once('=')?;
+            Some((decode_component(key), decode_component(value)))
+        })
+        .collect()
+}
+
+/// `query_params` of an image request. A key given twice once decoded, `width=400&%77idth=4999`,
+/// is refused rather than leaving each reader to pick one.
+pub fn unique_query_params(query: &str) -> Result<QueryParams, ErrorResponse> {
+    let mut params = QueryParams::new();
+    for pair in query.split('&') {
+        let Some((key, value)) = pair.split_once('=') else { continue };
+        if params.insert(decode_component(key), decode_component(value)).is_some() {
+            return Err(DuplicateParameterError {});
+        }
+    }
+    Ok(params)
+}
+
+/// `params` as a query string, sorted by key.
+pub fn encode_query(params: &QueryParams) -> String {
+    let mut pairs: Vec<(&String, &String)> = params.iter().collect();
+    pairs.sort();
+    pairs
+        .iter()
+        .map(|(key, value)| {
+            format!("{}={}", utf8_percent_encode(key, QUERY_VALUE), utf8_percent_encode(value, QUERY_VALUE))
+        })
+        .collect::<Vec<String>>()
+        .join("&")
+}
+
+fn decode_component(component: &str) -> String {
+    let component = component.replace('+', " ");
+    percent_decode_str(&component).decode_utf8_lossy().into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn query_params_are_decoded() {
+        let params = query_params("prefix=%2Fportfolio%2F&flag&a=b+c");
+        assert_eq!(params.get("prefix").unwrap(), "/portfolio/");
+        assert_eq!(params.get("a").unwrap(), "b c");
+        assert!(!params.contains_key("flag"))
+    }
+
+    #[test]
+    fn duplicate_keys_are_refused_once_decoded() {
+        assert!(matches!(unique_query_params("a=1&%61=2"), Err(DuplicateParameterError {})));
+        let params = unique_query_params("width=400&filters=sharpen%3A0.5&url=https%3A%2F%2Fa.com%2Fb.jpg").unwrap();
+        assert_eq!(encode_query(&params), "filters=sharpen:0.5&url=https:%2F%2Fa.com%2Fb.jpg&width=400")
+    }
+}
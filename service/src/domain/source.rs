@@ -0,0 +1,60 @@
+use crate::domain::error::ErrorResponse;
+use crate::domain::error::ErrorResponse::InvalidSourceError;
+use crate::domain::query::QueryParams;
+use reqwest::Url;
+
+/// Where the original of a request comes from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ImageSource {
+    /// Looked up in the repository chain.
+    Path(String),
+    /// Fetched from a third party server, named either by a `url=` parameter or by an
+    /// absolute URL as the path, e.g. `/https://cdn.example.com/a.jpg`.
+    Remote(Url),
+}
+
+impl ImageSource {
+    pub fn from_request(path: &str, params: &QueryParams) -> Result<ImageSource, ErrorResponse> {
+        let remote = params
+            .get("url")
+            .cloned()
+            .or_else(|| {
+                let absolute = path.strip_prefix('/')?;
+                (absolute.starts_with("http://") || absolute.starts_with("https://")).then(|| absolute.to_string())
+            });
+        match remote {
+            Some(url) => Url::parse(&url)
+                .map(ImageSource::Remote)
+                .map_err(|_| InvalidSourceError {}),
+            None => Ok(ImageSource::Path(path.to_string())),
+        }
+    }
+
+    /// Path whose extension gives the image format.
+    pub fn format_path(&self) -> &str {
+        match self {
+            ImageSource::Path(path) => path,
+            ImageSource::Remote(url) => url.path(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::query::query_params;
+
+    #[test]
+    fn source_from_request() {
+        assert_eq!(
+            ImageSource::from_request("/a.jpg", &query_params("width=10")).unwrap(),
+            ImageSource::Path("/a.jpg".to_string())
+        );
+        let url = Url::parse("https://cdn.example.com/b.png?v=2").unwrap();
+        let query = "width=10&url=https%3A%2F%2Fcdn.example.com%2Fb.png%3Fv%3D2";
+        assert_eq!(ImageSource::from_request("/remote", &query_params(query)).unwrap(), ImageSource::Remote(url));
+        let source = ImageSource::from_request("/https://cdn.example.com/c.webp", &QueryParams::new()).unwrap();
+        assert_eq!(source.format_path(), "/c.webp");
+        assert!(ImageSource::from_request("/x", &query_params("url=not-a-url")).is_err())
+    }
+}
@@ -0,0 +1,53 @@
+use serde::Serialize;
+
+/// Body of the status endpoint.
+#[derive(Debug, Serialize)]
+pub struct ServiceStatus {
+    pub status: &'static str,
+    pub origins: Vec<OriginStatus>,
+    pub cache_writes: Vec<CacheWriteStatus>,
+}
+
+/// Background cache writes of one mount, see `CacheWriter`.
+#[derive(Debug, Serialize)]
+pub struct CacheWriteStatus {
+    /// Prefix of the mount, `/` for the default chain.
+    pub mount: String,
+    #[serde(flatten)]
+    pub counts: CacheWriteCounts,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct CacheWriteCounts {
+    pub written: u64,
+    pub failed: u64,
+    /// Writes dropped because the queue was full.
+    pub dropped: u64,
+}
+
+/// Health of one origin as seen by its circuit breaker.
+#[derive(Debug, Serialize)]
+pub struct OriginStatus {
+    /// Prefix of the mount the origin serves, `/` for the default chain.
+    pub mount: String,
+    pub origin: &'static str,
+    #[serde(flatten)]
+    pub breaker: BreakerStatus,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct BreakerStatus {
+    pub state: CircuitState,
+    pub consecutive_failures: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitState {
+    /// Requests go through.
+    Closed,
+    /// Requests fail fast until the origin has had time to recover.
+    Open,
+    /// A single trial request is in flight, its outcome closes or reopens the circuit.
+    HalfOpen,
+}
@@ -0,0 +1,248 @@
+use crate::domain::dimension::Dimension;
+use crate::domain::error::ErrorResponse;
+use crate::domain::error::ErrorResponse::{
+    InvalidPathError, InvalidSignatureError, InvalidTransformError, UnsupportedFilterError,
+};
+use crate::domain::pipeline::{Axis, Operation, Pipeline};
+use crate::domain::transform::{parse_quality, Fit};
+use base64::engine::general_purpose::URL_SAFE;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use percent_encoding::percent_decode_str;
+use serde::Deserialize;
+use sha1::Sha1;
+use tracing::debug;
+
+/// First path segment of Thumbor URLs that carry no signature.
+const UNSAFE_SEGMENT: &str = "unsafe";
+/// Length of a Thumbor signature, the URL-safe base64 of a 20 byte HMAC-SHA1.
+const SIGNATURE_LENGTH: usize = 28;
+
+/// Serving of Thumbor URLs, `/<signature>/300x200/smart/filters:quality(80)/<path>`, for
+/// clients migrating off a Thumbor deployment.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ThumborConfig {
+    pub enabled: bool,
+    /// Also serve `/unsafe/` URLs, which carry no signature.
+    pub allow_unsafe: bool,
+}
+
+/// Split a Thumbor URL into its signature, `None` for `/unsafe/`, and the signed rest.
+/// Paths whose first segment is neither are not Thumbor URLs.
+pub fn split_thumbor_path(path: &str) -> Option<(Option<&str>, &str)> {
+    let (first, rest) = path.strip_prefix('/')?.split_once('/')?;
+    match first {
+        UNSAFE_SEGMENT => Some((None, rest)),
+        signature if signature.len() == SIGNATURE_LENGTH && signature.ends_with('=') => Some((Some(signature), rest)),
+        _ => None,
+    }
+}
+
+/// Check a Thumbor signature, the URL-safe base64 HMAC-SHA1 of everything after it, with
+/// the security key of the Thumbor deployment.
+pub fn verify_thumbor_signature(
+    config: &ThumborConfig,
+    key: Option<&[u8]>,
+    signature: Option<&str>,
+    rest: &str,
+) -> Result<(), ErrorResponse> {
+    let (Some(signature), Some(key)) = (signature, key) else {
+        return match signature.is_none() && config.allow_unsafe {
+            true => Ok(()),
+            false => Err(InvalidSignatureError {}),
+        };
+    };
+    let signature = URL_SAFE.decode(signature).map_err(|_| InvalidSignatureError {})?;
+    let mut mac = Hmac::<Sha1>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(rest.as_bytes());
+    mac.verify_slice(&signature).map_err(|_| {
+        debug!("Invalid Thumbor signature for {rest}");
+        InvalidSignatureError {}
+    })
+}
+
+/// Parse the part of a Thumbor URL after the signature into a pipeline and the image path:
+/// `[AxB:CxD/][fit-in/][-]Wx[-]H/[halign/][valign/][smart/][filters:f(a):g(b)/]<image>`.
+/// Alignment and `smart` are accepted but crops stay centered.
+pub fn parse_thumbor(rest: &str) -> Result<(Pipeline, String), ErrorResponse> {
+    let mut segments = rest.split('/').peekable();
+    let mut next_if = |matches: fn(&str) -> bool| segments.next_if(|segment| matches(segment));
+    let crop = next_if(|segment| parse_crop(segment).is_some()).and_then(parse_crop);
+    let fit = match next_if(|segment| segment.ends_with("fit-in")) {
+        Some("fit-in") => Fit::Contain,
+        Some(_) => return Err(InvalidTransformError {}),
+        None => Fit::Cover,
+    };
+    let size = next_if(|segment| parse_size(segment).is_some()).and_then(parse_size);
+    next_if(|segment| matches!(segment, "left" | "center" | "right"));
+    next_if(|segment| matches!(segment, "top" | "middle" | "bottom"));
+    next_if(|segment| segment == "smart");
+    let filters = next_if(|segment| segment.starts_with("filters:")).map(|segment| &segment["filters:".len()..]);
+    if next_if(|segment| segment == "meta" || segment.starts_with("trim")).is_some() {
+        return Err(InvalidTransformError {});
+    }
+
+    let mut operations: Vec<Operation> = crop.into_iter().collect();
+    if let Some(((width, flip_h), (height, flip_v))) = size {
+        let dimension = match (width, height) {
+            (0, 0) => None,
+            (width, 0) => Some(Dimension::Width(width)),
+            (0, height) => Some(Dimension::Height(height)),
+            (width, height) => Some(Dimension::Bounds { width, height }),
+        };
+        operations.extend(dimension.map(|dimension| Operation::Resize { dimension, fit }));
+        operations.extend(flip_h.then_some(Operation::Flip(Axis::Horizontal)));
+        operations.extend(flip_v.then_some(Operation::Flip(Axis::Vertical)));
+    }
+    for filter in filters.map(split_filters).transpose()?.unwrap_or_default() {
+        operations.extend(parse_filter(filter)?);
+    }
+
+    let image: Vec<&str> = segments.collect();
+    let image = image.join("/");
+    if image.is_empty() {
+        return Err(InvalidPathError {});
+    }
+    // Remote images are given whole, often percent-encoded.
+    let image = match image.starts_with("http%3A") || image.starts_with("https%3A") {
+        true => percent_decode_str(&image).decode_utf8().map_err(|_| InvalidPathError {})?.into_owned(),
+        false => image,
+    };
+    Ok((Pipeline { operations }, format!("/{image}")))
+}
+
+/// `AxB:CxD`, the left, top, right and bottom of a crop.
+fn parse_crop(segment: &str) -> Option<Operation> {
+    let (top_left, bottom_right) = segment.split_once(':')?;
+    let (left, top) = top_left.split_once('x')?;
+    let (right, bottom) = bottom_right.split_once('x')?;
+    let [left, top, right, bottom] = [left, top, right, bottom].map(|side| side.parse::<u32>().ok());
+    let (left, top, right, bottom) = (left?, top?, right?, bottom?);
+    (right > left && bottom > top).then_some(Operation::Crop {
+        x: left,
+        y: top,
+        width: right - left,
+        height: bottom - top,
+    })
+}
+
+/// `WxH`, either side `0`, empty or `orig` to keep the aspect ratio, negative to flip.
+fn parse_size(segment: &str) -> Option<((u32, bool), (u32, bool))> {
+    let side = |side: &str| {
+        let (flip, side) = match side.strip_prefix('-') {
+            Some(side) => (true, side),
+            None => (false, side),
+        };
+        match side {
+            "" | "orig" => Some((0, flip)),
+            side => side.parse::<u32>().ok().map(|side| (side, flip)),
+        }
+    };
+    let (width, height) = segment.split_once('x')?;
+    Some((side(width)?, side(height)?))
+}
+
+/// `quality(80):grayscale()` as name and arguments pairs.
+fn split_filters(filters: &str) -> Result<Vec<(&str, &str)>, ErrorResponse> {
+    let mut parsed = Vec::new();
+    let mut rest = filters;
+    while !rest.is_empty() {
+        let (name, after) = rest.split_once('(').ok_or(InvalidTransformError {})?;
+        let (arguments, after) = after.split_once(')').ok_or(InvalidTransformError {})?;
+        parsed.push((name, arguments));
+        rest = after.strip_prefix(':').unwrap_or(after);
+    }
+    Ok(parsed)
+}
+
+/// The operation a Thumbor filter stands for, `None` for those with nothing to do here.
+fn parse_filter((name, arguments): (&str, &str)) -> Result<Option<Operation>, ErrorResponse> {
+    let arguments: Vec<&str> = arguments.split(',').map(str::trim).collect();
+    let operation = match (name, &arguments[..]) {
+        ("quality", [quality]) => Operation::Quality(parse_quality(quality)?),
+        ("format", [format]) => Operation::Format(format.parse()?),
+        ("grayscale", [""]) => Operation::Filter("grayscale".parse()?),
+        ("blur", [radius]) | ("blur", [radius, ""]) => Operation::Filter(format!("blur:{radius}").parse()?),
+        ("blur", [_, sigma]) => Operation::Filter(format!("blur:{sigma}").parse()?),
+        ("sharpen", [_, radius, _]) => Operation::Filter(format!("sharpen:{radius}").parse()?),
+        // Thumbor rotates counter-clockwise.
+        ("rotate", ["0"]) => return Ok(None),
+        ("rotate", ["90"]) => Operation::Rotate(270),
+        ("rotate", ["180"]) => Operation::Rotate(180),
+        ("rotate", ["270"]) => Operation::Rotate(90),
+        // Nothing is carried over from the original but its pixels.
+        ("strip_icc" | "strip_exif", [""]) => return Ok(None),
+        ("quality" | "format" | "grayscale" | "blur" | "sharpen" | "rotate" | "strip_icc" | "strip_exif", _) => {
+            return Err(InvalidTransformError {})
+        }
+        (name, _) => {
+            debug!("Unsupported Thumbor filter {name}");
+            return Err(UnsupportedFilterError { filter: name.to_string() });
+        }
+    };
+    Ok(Some(operation))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::transform::{Filter, OutputFormat};
+
+    #[test]
+    fn thumbor_urls_parse_into_pipelines() {
+        let (pipeline, image) = parse_thumbor("300x200/smart/filters:quality(80):format(webp)/photos/a.jpg").unwrap();
+        assert_eq!(image, "/photos/a.jpg");
+        assert_eq!(
+            pipeline.operations,
+            vec![
+                Operation::Resize { dimension: Dimension::Bounds { width: 300, height: 200 }, fit: Fit::Cover },
+                Operation::Quality(80),
+                Operation::Format(OutputFormat::Webp),
+            ]
+        );
+
+        let (pipeline, image) = parse_thumbor("10x20:110x220/fit-in/-300x0/filters:blur(2):rotate(90)/https%3A%2F%2Fcdn.example.com%2Fa.jpg").unwrap();
+        assert_eq!(image, "/https://cdn.example.com/a.jpg");
+        assert_eq!(
+            pipeline.operations,
+            vec![
+                Operation::Crop { x: 10, y: 20, width: 100, height: 200 },
+                Operation::Resize { dimension: Dimension::Width(300), fit: Fit::Contain },
+                Operation::Flip(Axis::Horizontal),
+                Operation::Filter(Filter::Blur(2.0)),
+                Operation::Rotate(270),
+            ]
+        );
+        assert_eq!(parse_thumbor("a.jpg").unwrap().0, Pipeline::default())
+    }
+
+    #[test]
+    fn thumbor_refuses_unsupported_filters() {
+        match parse_thumbor("300x200/filters:watermark(a.png,0,0,50)/a.jpg") {
+            Err(UnsupportedFilterError { filter }) => assert_eq!(filter, "watermark"),
+            other => panic!("watermark was not refused: {other:?}"),
+        }
+        for rest in ["filters:quality(101)/a.jpg", "filters:rotate(45)/a.jpg", "trim/a.jpg", "300x200/"] {
+            assert!(parse_thumbor(rest).is_err(), "{rest} was accepted");
+        }
+    }
+
+    #[test]
+    fn thumbor_signatures_verify() {
+        let config = ThumborConfig { enabled: true, allow_unsafe: false };
+        let rest = "300x200/smart/a.jpg";
+        let mut mac = Hmac::<Sha1>::new_from_slice(b"MY_SECURE_KEY").unwrap();
+        mac.update(rest.as_bytes());
+        let signature = URL_SAFE.encode(mac.finalize().into_bytes());
+        let path = format!("/{signature}/{rest}");
+
+        let (signature, signed) = split_thumbor_path(&path).unwrap();
+        assert!(verify_thumbor_signature(&config, Some(b"MY_SECURE_KEY"), signature, signed).is_ok());
+        assert!(verify_thumbor_signature(&config, Some(b"OTHER_KEY"), signature, signed).is_err());
+        assert!(verify_thumbor_signature(&config, Some(b"MY_SECURE_KEY"), None, signed).is_err());
+        let allow_unsafe = ThumborConfig { allow_unsafe: true, ..config };
+        assert!(verify_thumbor_signature(&allow_unsafe, None, None, signed).is_ok());
+        assert_eq!(split_thumbor_path("/photos/a.jpg"), None)
+    }
+}
@@ -0,0 +1,207 @@
+use crate::domain::dimension::Dimension;
+use crate::domain::error::ErrorResponse;
+use crate::domain::error::ErrorResponse::InvalidTransformError;
+use crate::domain::query::QueryParams;
+use image::ImageFormat;
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+const MAX_BLUR_SIGMA: f32 = 50.0;
+const MAX_SHARPEN_SIGMA: f32 = 10.0;
+
+/// How an image is fitted into a width and height given together.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Fit {
+    /// Scaled to fit inside both, keeping its aspect ratio.
+    #[default]
+    Contain,
+    /// Scaled to cover both and cropped around the center.
+    Cover,
+    /// Stretched to both.
+    Fill,
+}
+
+impl FromStr for Fit {
+    type Err = ErrorResponse;
+
+    fn from_str(fit: &str) -> Result<Self, Self::Err> {
+        match fit {
+            "contain" => Ok(Fit::Contain),
+            "cover" => Ok(Fit::Cover),
+            "fill" => Ok(Fit::Fill),
+            _ => Err(InvalidTransformError {}),
+        }
+    }
+}
+
+impl Display for Fit {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Fit::Contain => write!(f, "contain"),
+            Fit::Cover => write!(f, "cover"),
+            Fit::Fill => write!(f, "fill"),
+        }
+    }
+}
+
+/// Formats images can be converted to.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    Jpeg,
+    Png,
+    Webp,
+    Gif,
+}
+
+impl FromStr for OutputFormat {
+    type Err = ErrorResponse;
+
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format {
+            "jpeg" | "jpg" => Ok(OutputFormat::Jpeg),
+            "png" => Ok(OutputFormat::Png),
+            "webp" => Ok(OutputFormat::Webp),
+            "gif" => Ok(OutputFormat::Gif),
+            _ => Err(InvalidTransformError {}),
+        }
+    }
+}
+
+impl Display for OutputFormat {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OutputFormat::Jpeg => write!(f, "jpeg"),
+            OutputFormat::Png => write!(f, "png"),
+            OutputFormat::Webp => write!(f, "webp"),
+            OutputFormat::Gif => write!(f, "gif"),
+        }
+    }
+}
+
+impl From<OutputFormat> for ImageFormat {
+    fn from(format: OutputFormat) -> Self {
+        match format {
+            OutputFormat::Jpeg => ImageFormat::Jpeg,
+            OutputFormat::Png => ImageFormat::Png,
+            OutputFormat::Webp => ImageFormat::WebP,
+            OutputFormat::Gif => ImageFormat::Gif,
+        }
+    }
+}
+
+/// Filters applied after resizing, written `blur:2`, `sharpen:1` or `grayscale`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum Filter {
+    /// Gaussian blur with the given sigma.
+    Blur(f32),
+    /// Unsharp mask with the given sigma.
+    Sharpen(f32),
+    Grayscale,
+}
+
+impl FromStr for Filter {
+    type Err = ErrorResponse;
+
+    fn from_str(filter: &str) -> Result<Self, Self::Err> {
+        let (name, argument) = filter.split_once(':').unwrap_or((filter, ""));
+        let sigma = |max: f32| match argument.parse::<f32>() {
+            Ok(sigma) if sigma > 0.0 && sigma <= max => Ok(sigma),
+            _ => Err(InvalidTransformError {}),
+        };
+        match name {
+            "blur" => Ok(Filter::Blur(sigma(MAX_BLUR_SIGMA)?)),
+            "sharpen" => Ok(Filter::Sharpen(sigma(MAX_SHARPEN_SIGMA)?)),
+            "grayscale" if argument.is_empty() => Ok(Filter::Grayscale),
+            _ => Err(InvalidTransformError {}),
+        }
+    }
+}
+
+impl TryFrom<String> for Filter {
+    type Error = String;
+
+    fn try_from(filter: String) -> Result<Self, Self::Error> {
+        filter.parse().map_err(|_| format!("unknown filter \"{filter}\""))
+    }
+}
+
+impl From<Filter> for String {
+    fn from(filter: Filter) -> Self {
+        filter.to_string()
+    }
+}
+
+impl Display for Filter {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Filter::Blur(sigma) => write!(f, "blur:{sigma}"),
+            Filter::Sharpen(sigma) => write!(f, "sharpen:{sigma}"),
+            Filter::Grayscale => write!(f, "grayscale"),
+        }
+    }
+}
+
+/// Everything done to an original before it is served. Every URL syntax the service
+/// understands is parsed into one of these.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Transform {
+    pub dimension: Option<Dimension>,
+    pub fit: Fit,
+    /// Encode as this format rather than that of the original.
+    pub format: Option<OutputFormat>,
+    /// JPEG quality from 1 to 100, other formats are encoded losslessly.
+    pub quality: Option<u8>,
+    pub filters: Vec<Filter>,
+}
+
+impl Transform {
+    /// Read `fit`, `format`, `quality` and a comma-separated `filters` from query parameters,
+    /// the dimension is decoded separately as it is subject to the dimension policy.
+    pub fn from_params(params: &QueryParams, dimension: Option<Dimension>) -> Result<Transform, ErrorResponse> {
+        let param = |name: &str| params.get(name).filter(|value| !value.is_empty());
+        Ok(Transform {
+            dimension,
+            fit: param("fit").map(|fit| fit.parse()).transpose()?.unwrap_or_default(),
+            format: param("format").map(|format| format.parse()).transpose()?,
+            quality: param("quality").map(|quality| parse_quality(quality)).transpose()?,
+            filters: param("filters")
+                .map(|filters| filters.split(',').map(str::parse).collect::<Result<Vec<Filter>, _>>())
+                .transpose()?
+                .unwrap_or_default(),
+        })
+    }
+}
+
+pub fn parse_quality(quality: &str) -> Result<u8, ErrorResponse> {
+    match quality.parse::<u8>() {
+        Ok(quality) if (1..=100).contains(&quality) => Ok(quality),
+        _ => Err(InvalidTransformError {}),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::query::query_params;
+
+    #[test]
+    fn transform_from_query() {
+        let transform = Transform::from_params(&query_params("fit=cover&format=webp&quality=80&filters=blur:2,grayscale"), None).unwrap();
+        assert_eq!(transform.fit, Fit::Cover);
+        assert_eq!(transform.format, Some(OutputFormat::Webp));
+        assert_eq!(transform.quality, Some(80));
+        assert_eq!(transform.filters, vec![Filter::Blur(2.0), Filter::Grayscale]);
+        assert_eq!(Transform::from_params(&query_params("width=10"), None).unwrap(), Transform::default())
+    }
+
+    #[test]
+    fn transform_rejects_unknown_values() {
+        for query in ["fit=squash", "format=bmp", "quality=0", "quality=101", "filters=sepia", "filters=blur:500"] {
+            assert!(matches!(Transform::from_params(&query_params(query), None), Err(InvalidTransformError {})), "{query} was accepted");
+        }
+    }
+}
@@ -0,0 +1,38 @@
+use crate::domain::dimension::Dimension;
+use serde::Deserialize;
+
+/// Limits on the transforms requested for a mount.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct TransformPolicy {
+    pub max_width: Option<u32>,
+    pub max_height: Option<u32>,
+}
+
+impl TransformPolicy {
+    pub fn allows(&self, dimension: &Dimension) -> bool {
+        match dimension {
+            Dimension::Width(width) => self.max_width.is_none_or(|max| *width <= max),
+            Dimension::Height(height) => self.max_height.is_none_or(|max| *height <= max),
+            Dimension::Bounds { width, height } => {
+                self.allows(&Dimension::Width(*width)) && self.allows(&Dimension::Height(*height))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn policy_limits_dimensions() {
+        let policy = TransformPolicy {
+            max_width: Some(800),
+            max_height: None,
+        };
+        assert!(policy.allows(&Dimension::Width(800)));
+        assert!(!policy.allows(&Dimension::Width(801)));
+        assert!(policy.allows(&Dimension::Height(5000)))
+    }
+}
@@ -0,0 +1,63 @@
+use image::ImageFormat;
+use serde::{Deserialize, Serialize};
+
+/// What uploaded originals are accepted.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct UploadConfig {
+    pub max_bytes: u64,
+    pub max_width: u32,
+    pub max_height: u32,
+    /// Accepted formats by extension, e.g. `"jpeg"` or `"webp"`.
+    pub formats: Vec<String>,
+}
+
+impl Default for UploadConfig {
+    fn default() -> Self {
+        UploadConfig {
+            max_bytes: 25 * 1024 * 1024,
+            max_width: 8192,
+            max_height: 8192,
+            formats: ["jpeg", "png", "webp", "gif"].map(String::from).to_vec(),
+        }
+    }
+}
+
+impl UploadConfig {
+    pub fn allows_format(&self, format: ImageFormat) -> bool {
+        self.formats
+            .iter()
+            .any(|name| ImageFormat::from_extension(name) == Some(format))
+    }
+
+    pub fn allows_dimensions(&self, width: u32, height: u32) -> bool {
+        width <= self.max_width && height <= self.max_height
+    }
+}
+
+/// Body of a successful upload.
+#[derive(Debug, Serialize)]
+pub struct StoredImage {
+    pub path: String,
+    pub origin: &'static str,
+    pub content_type: String,
+    pub width: u32,
+    pub height: u32,
+    pub bytes: u64,
+    pub etag: Option<String>,
+    pub generation: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn upload_policy_checks_format_and_size() {
+        let config = UploadConfig::default();
+        assert!(config.allows_format(ImageFormat::Jpeg));
+        assert!(!config.allows_format(ImageFormat::Tiff));
+        assert!(config.allows_dimensions(8192, 10));
+        assert!(!config.allows_dimensions(8193, 10))
+    }
+}
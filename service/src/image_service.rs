@@ -1,14 +1,26 @@
 use crate::domain::dimension::Dimension;
-use crate::domain::dimension::Dimension::{Height, Width};
+use crate::domain::dimension::Dimension::{Bounds, Height, Width};
 use crate::domain::error::ErrorResponse;
-use crate::domain::error::ErrorResponse::ImageDecodeError;
-use crate::domain::format_from_path;
-use crate::repository::ImageRepository;
-use crate::{BUCKET_REPOSITORY, VOLUME_REPOSITORY};
+use crate::client::BucketResponse;
+use crate::domain::error::ErrorResponse::{
+    DimensionNotAllowedError, ImageDecodeError, ImageNotFoundError, ImageTooLargeError, InvalidTransformError,
+};
+use crate::domain::image_limits::ImageLimits;
+use crate::domain::pipeline::{Axis, Operation, Pipeline};
+use crate::domain::server_timing::timing::Timing;
+use crate::domain::transform::{Filter, Fit};
+use crate::domain::image_item::ImageItem;
+use crate::domain::source::ImageSource;
+use crate::repository::chain::RepositoryChain;
+use crate::REMOTE_CLIENT;
+use reqwest::Url;
 use fast_image_resize::{FilterType, ResizeAlg, ResizeOptions, Resizer, SrcCropping};
-use image::{DynamicImage, ImageFormat, ImageReader};
+use image::codecs::jpeg::JpegEncoder;
+use image::{DynamicImage, ImageError, ImageFormat, ImageReader};
 use std::io::{BufReader, Cursor};
-use tracing::{debug, instrument, };
+use std::sync::Arc;
+use std::time::Instant;
+use tracing::{instrument, warn};
 use futures_util::{stream, StreamExt};
 use hyper::body::{Bytes, Frame};
 use http_body_util::combinators::{BoxBody};
@@ -21,65 +33,182 @@ const RESIZE_OPTS: ResizeOptions = ResizeOptions {
     mul_div_alpha: true,
 };
 
-/// Get image from provided path, it attempts:
-///     1. Volume cache
-///     2. Bucket (HTTP/2)
+/// Get image from provided path through the repository chain of its mount, by default:
+///     1. Volume cache, revalidating stale entries in the background
+///     2. Bucket (HTTP/2), populating the volume cache in the background
+#[instrument(skip(chain))]
+pub async fn get_image(chain: &Arc<RepositoryChain>, path: &str) -> Result<ImageItem, ErrorResponse> {
+    chain.get_image(path).await
+}
+
+/// Get the original of a request from wherever its source points.
+pub async fn get_source_image(chain: &Arc<RepositoryChain>, source: &ImageSource) -> Result<ImageItem, ErrorResponse> {
+    match source {
+        ImageSource::Path(path) => get_image(chain, path).await,
+        ImageSource::Remote(url) => get_remote_image(url).await,
+    }
+}
+
+/// Fetch an image from an allowlisted third party server, not cached in the chain.
 #[instrument]
-pub async fn get_image(path: &str) -> Result<(DynamicImage, ImageFormat), ErrorResponse> {
-    let image_bytes: Vec<u8> = match VOLUME_REPOSITORY.read_image(path).await.ok() {
-        Some(item) => item,
-        None => {
-            let bucket_item = BUCKET_REPOSITORY.read_image(path).await?;
-            VOLUME_REPOSITORY.write_image(&path, &bucket_item).await?;
-            bucket_item
+pub async fn get_remote_image(url: &Url) -> Result<ImageItem, ErrorResponse> {
+    match REMOTE_CLIENT.get(url).await {
+        Ok(BucketResponse::Fetched(bytes, metadata)) => Ok(ImageItem { bytes, metadata }),
+        Ok(_) => Err(ImageNotFoundError {}),
+        Err(e) => {
+            warn!("Could not fetch {url}: {e}");
+            Err(e.into())
         }
-    };
-
-    let image = decode_image(image_bytes, format_from_path(path))?;
-    debug!("Image decoded at {path}");
-    Ok((image, format_from_path(&path)))
+    }
 }
 
-/// Resize an image based on a provided `Dimension`.
+/// Resize an image based on a provided `Dimension`, fitted into it as `fit` says.
 /// TODO make output Vec<u8>
 #[instrument(skip(src_image))]
-pub fn resize_image(dimension: Dimension, src_image: DynamicImage) -> DynamicImage {
-    let mut dst_image: DynamicImage;
+pub fn resize_image(dimension: Dimension, fit: Fit, src_image: DynamicImage) -> DynamicImage {
     let mut resizer: Resizer = Resizer::new();
-    match dimension {
-        Width(new_width) => {
-            let new_height =
-                ((new_width * src_image.height()) as f64 / src_image.width() as f64) as u32;
-            dst_image = DynamicImage::new(new_width, new_height, src_image.color());
-        }
-        Height(new_height) => {
-            let new_width =
-                ((new_height * src_image.width()) as f64 / src_image.height() as f64) as u32;
-            dst_image = DynamicImage::new(new_width, new_height, src_image.color());
-        }
+    let (new_width, new_height) = target_size(&dimension, fit, src_image.width(), src_image.height());
+    let mut dst_image = DynamicImage::new(new_width, new_height, src_image.color());
+    let options = match fit {
+        Fit::Fill => ResizeOptions {
+            cropping: SrcCropping::None,
+            ..RESIZE_OPTS
+        },
+        Fit::Contain | Fit::Cover => RESIZE_OPTS,
     };
-    let _ = resizer.resize(&src_image, &mut dst_image, &RESIZE_OPTS);
+    let _ = resizer.resize(&src_image, &mut dst_image, &options);
     dst_image
 }
 
-/// Decode bytes to `DynamicImage`.
-#[instrument(skip(image_bytes))]
-pub fn decode_image(image_bytes: Vec<u8>, format: ImageFormat) -> Result<DynamicImage, ErrorResponse> {
+/// Size of the image `dimension` resizes a `width` by `height` image to. A single side keeps
+/// the aspect ratio, both sides are either fitted inside or filled according to `fit`.
+pub fn target_size(dimension: &Dimension, fit: Fit, width: u32, height: u32) -> (u32, u32) {
+    let scale = |side: u32, other: u32, other_target: u32| {
+        // Never rounded down to nothing, however thin the original.
+        ((side as f64 * other_target as f64 / other.max(1) as f64) as u32).max(1)
+    };
+    match *dimension {
+        Width(new_width) => (new_width, scale(height, width, new_width)),
+        Height(new_height) => (scale(width, height, new_height), new_height),
+        Bounds { width: new_width, height: new_height } => match fit {
+            Fit::Cover | Fit::Fill => (new_width, new_height),
+            // Whichever side reaches its bound first.
+            Fit::Contain if new_width as u64 * height as u64 <= new_height as u64 * width as u64 => {
+                (new_width, scale(height, width, new_width))
+            }
+            Fit::Contain => (scale(width, height, new_height), new_height),
+        },
+    }
+}
+
+/// Sizes the image goes through in `pipeline`, from an original of `width` by `height`. Refuses
+/// crops outside the image, empty steps and steps over the output limits before any pixels
+/// are touched.
+pub fn check_pipeline(pipeline: &Pipeline, width: u32, height: u32, limits: &ImageLimits) -> Result<(), ErrorResponse> {
+    pipeline.operations.iter().try_fold((width, height), |(width, height), operation| {
+        let (width, height) = match *operation {
+            Operation::Crop { x, y, width: crop_width, height: crop_height } => {
+                if x as u64 + crop_width as u64 > width as u64 || y as u64 + crop_height as u64 > height as u64 {
+                    return Err(InvalidTransformError {});
+                }
+                (crop_width, crop_height)
+            }
+            Operation::Resize { ref dimension, fit } => target_size(dimension, fit, width, height),
+            Operation::Rotate(90 | 270) => (height, width),
+            _ => (width, height),
+        };
+        if width == 0 || height == 0 {
+            return Err(InvalidTransformError {});
+        }
+        match limits.allows_output(width, height) {
+            true => Ok((width, height)),
+            false => Err(DimensionNotAllowedError {}),
+        }
+    })?;
+    Ok(())
+}
+
+/// Run the operations of `pipeline` in order, timing each one. Format and quality only
+/// matter to `encode_image`.
+#[instrument(skip(image))]
+pub fn run_pipeline(image: DynamicImage, pipeline: &Pipeline) -> (DynamicImage, Vec<Timing>) {
+    let mut timings = Vec::with_capacity(pipeline.operations.len());
+    let image = pipeline.operations.iter().enumerate().fold(image, |image, (index, operation)| {
+        let timer = Instant::now();
+        let image = apply_operation(image, operation);
+        timings.push(Timing::new(&format!("op{index}"), timer.elapsed(), Some(operation.to_string())));
+        image
+    });
+    (image, timings)
+}
+
+fn apply_operation(image: DynamicImage, operation: &Operation) -> DynamicImage {
+    match *operation {
+        Operation::Crop { x, y, width, height } => image.crop_imm(x, y, width, height),
+        Operation::Resize { ref dimension, fit } => resize_image(dimension.clone(), fit, image),
+        Operation::Rotate(90) => image.rotate90(),
+        Operation::Rotate(180) => image.rotate180(),
+        Operation::Rotate(270) => image.rotate270(),
+        Operation::Flip(Axis::Horizontal) => image.fliph(),
+        Operation::Flip(Axis::Vertical) => image.flipv(),
+        Operation::Filter(Filter::Blur(sigma)) => image.blur(sigma),
+        Operation::Filter(Filter::Sharpen(sigma)) => image.unsharpen(sigma, 1),
+        Operation::Filter(Filter::Grayscale) => image.grayscale(),
+        Operation::Rotate(_) | Operation::Format(_) | Operation::Quality(_) => image,
+    }
+}
+
+/// Decode bytes to `DynamicImage`, checking the dimensions declared in the header against
+/// `limits` before any pixels are allocated.
+#[instrument(skip(image_bytes, limits))]
+pub fn decode_image(image_bytes: Bytes, format: ImageFormat, limits: &ImageLimits) -> Result<DynamicImage, ErrorResponse> {
+    let (width, height) = ImageReader::with_format(Cursor::new(&image_bytes), format)
+        .into_dimensions()
+        .map_err(|_| ImageDecodeError {})?;
+    if !limits.allows_input(width, height) {
+        warn!("Refusing to decode a {width}x{height} image");
+        return Err(ImageTooLargeError {});
+    }
     let cursor = Cursor::new(image_bytes);
     let mut reader = BufReader::new(cursor);
-    ImageReader::with_format(&mut reader, format)
+    let mut image_reader = ImageReader::with_format(&mut reader, format);
+    image_reader.limits(limits.decoder_limits());
+    image_reader
         .decode()
-        .map_err(|_| {
-            ImageDecodeError {}
+        .map_err(|e| match e {
+            ImageError::Limits(_) => ImageTooLargeError {},
+            _ => ImageDecodeError {},
         })
 }
 
-/// Take a dynamic image and write it as `Bytes`.
+/// Read the format and dimensions from the image header, without decoding the pixels.
+#[instrument(skip(image_bytes))]
+pub fn inspect_image(image_bytes: &Bytes) -> Result<(ImageFormat, u32, u32), ErrorResponse> {
+    let reader = ImageReader::new(Cursor::new(image_bytes))
+        .with_guessed_format()
+        .map_err(|_| ImageDecodeError {})?;
+    let format = reader.format().ok_or(ImageDecodeError {})?;
+    let (width, height) = reader.into_dimensions().map_err(|_| ImageDecodeError {})?;
+    Ok((format, width, height))
+}
+
+/// Take a dynamic image and write it as `Bytes`, `quality` applies to JPEG only.
 #[instrument(skip(image))]
-pub fn encode_image(image: DynamicImage, format: ImageFormat) -> Result<Vec<u8>, ErrorResponse> {
+pub fn encode_image(image: DynamicImage, format: ImageFormat, quality: Option<u8>) -> Result<Vec<u8>, ErrorResponse> {
     let mut bytes: Vec<u8> = Vec::new();
     let mut cursor = Cursor::new(&mut bytes);
-    image.write_to(&mut cursor, format).map_err(|_| {
+    // JPEG has no alpha channel, converting from formats that do drops it.
+    let image = match format {
+        ImageFormat::Jpeg if image.color().has_alpha() => DynamicImage::ImageRgb8(image.to_rgb8()),
+        _ => image,
+    };
+    let result = match (format, quality) {
+        (ImageFormat::Jpeg, Some(quality)) => {
+            image.write_with_encoder(JpegEncoder::new_with_quality(&mut cursor, quality))
+        }
+        _ => image.write_to(&mut cursor, format),
+    };
+    result.map_err(|_| {
         ImageWriteError {}
     })?;
     Ok(bytes)
@@ -91,4 +220,71 @@ pub fn image_to_body(image_bytes: Vec<u8>) -> BoxBody<Bytes, hyper::Error> {
         .chunks(8192)
         .map(|x| Ok::<Frame<Bytes>, hyper::Error>(Frame::data(Bytes::from(x))));
     BoxBody::new(StreamBody::new(chunked))
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn png(width: u32, height: u32) -> Bytes {
+        Bytes::from(encode_image(DynamicImage::new_rgb8(width, height), ImageFormat::Png, None).unwrap())
+    }
+
+    #[test]
+    fn decode_refuses_images_over_the_limits() {
+        let limits = ImageLimits {
+            max_input_pixels: 100,
+            ..ImageLimits::default()
+        };
+        assert!(decode_image(png(10, 10), ImageFormat::Png, &limits).is_ok());
+        assert!(matches!(decode_image(png(10, 11), ImageFormat::Png, &limits), Err(ImageTooLargeError {})));
+
+        let limits = ImageLimits {
+            max_alloc_bytes: 64,
+            ..ImageLimits::default()
+        };
+        assert!(matches!(decode_image(png(10, 10), ImageFormat::Png, &limits), Err(ImageTooLargeError {})))
+    }
+
+    #[test]
+    fn target_size_keeps_aspect_ratio() {
+        assert_eq!(target_size(&Width(400), Fit::Contain, 1600, 900), (400, 225));
+        assert_eq!(target_size(&Height(100), Fit::Contain, 1, 40_000), (1, 100));
+        assert_eq!(target_size(&Width(4096), Fit::Contain, 1, 40_000), (4096, 163_840_000))
+    }
+
+    #[test]
+    fn target_size_fits_bounds() {
+        let bounds = Bounds { width: 400, height: 400 };
+        assert_eq!(target_size(&bounds, Fit::Contain, 1600, 900), (400, 225));
+        assert_eq!(target_size(&bounds, Fit::Contain, 900, 1600), (225, 400));
+        assert_eq!(target_size(&bounds, Fit::Cover, 1600, 900), (400, 400))
+    }
+
+    #[test]
+    fn encode_converts_formats() {
+        let image = DynamicImage::new_rgba8(4, 4);
+        let jpeg = encode_image(image.clone(), ImageFormat::Jpeg, Some(50)).unwrap();
+        assert_eq!(image::guess_format(&jpeg).unwrap(), ImageFormat::Jpeg);
+        let pipeline = Pipeline { operations: vec![Operation::Filter(Filter::Blur(1.0)), Operation::Filter(Filter::Grayscale)] };
+        let (gray, timings) = run_pipeline(image, &pipeline);
+        assert!(!gray.color().has_color());
+        assert_eq!(timings.len(), 2)
+    }
+
+    #[test]
+    fn pipeline_checks_sizes_at_every_step() {
+        let limits = ImageLimits::default();
+        let (pipeline, _) = Pipeline::parse_path("/crop:0,0,800,600/rotate:90/resize:w=300/a.jpg").unwrap();
+        assert!(check_pipeline(&pipeline, 1000, 1000, &limits).is_ok());
+        assert!(matches!(check_pipeline(&pipeline, 700, 1000, &limits), Err(InvalidTransformError {})));
+        let (pipeline, _) = Pipeline::parse_path("/resize:w=100,h=100,fit=fill/resize:w=9000/a.jpg").unwrap();
+        assert!(matches!(check_pipeline(&pipeline, 1000, 1000, &limits), Err(DimensionNotAllowedError {})));
+        let empty = Pipeline { operations: vec![Operation::Resize { dimension: Width(0), fit: Fit::Contain }] };
+        assert!(matches!(check_pipeline(&empty, 1000, 1000, &limits), Err(InvalidTransformError {})));
+
+        let image = DynamicImage::new_rgb8(1000, 500);
+        let (pipeline, _) = Pipeline::parse_path("/crop:100,0,400,500/rotate:90/resize:w=250/a.jpg").unwrap();
+        let (resized, _) = run_pipeline(image, &pipeline);
+        assert_eq!((resized.width(), resized.height()), (250, 200))
+    }
+}
@@ -1,4 +1,8 @@
-use crate::repository::bucket_repository::BucketRepository;
+use crate::client::remote_client::RemoteClient;
+use crate::config::Config;
+use crate::repository::chain::{LayerSpec, RepositoryChain};
+use crate::repository::filesystem_repository::watch_for_changes;
+use crate::repository::mount::{MountTable, OriginSpec};
 use crate::repository::volume_repository::VolumeRepository;
 use crate::router::router;
 use hyper::server::conn::http2;
@@ -6,11 +10,15 @@ use hyper::service::service_fn;
 use hyper_util::rt::TokioIo;
 use lazy_static::lazy_static;
 use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
 use tokio::net::TcpListener;
-use tracing::{debug, info};
+use tracing::{debug, error, info};
 use crate::observability::init_tracing;
 
+mod admin_service;
 mod client;
+mod config;
 mod domain;
 mod image_service;
 mod observability;
@@ -20,8 +28,11 @@ mod router;
 mod service;
 
 lazy_static! {
-    static ref VOLUME_REPOSITORY: VolumeRepository = VolumeRepository {};
-    static ref BUCKET_REPOSITORY: BucketRepository = BucketRepository {};
+    static ref CONFIG: Config = Config::load();
+    static ref REPOSITORY_CHAIN: Arc<RepositoryChain> =
+        Arc::new(RepositoryChain::from_specs(&CONFIG.repository.chain, &CONFIG));
+    static ref MOUNT_TABLE: MountTable = MountTable::from_config(&CONFIG, REPOSITORY_CHAIN.clone());
+    static ref REMOTE_CLIENT: RemoteClient = RemoteClient::new(CONFIG.remote.clone(), CONFIG.origin.clone());
 }
 
 #[derive(Clone)]
@@ -40,10 +51,39 @@ where
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
 
-    let _ = rustls::crypto::ring::default_provider().install_default().unwrap();
+    rustls::crypto::ring::default_provider().install_default().unwrap();
 
     let _ = init_tracing().await;
 
+    lazy_static::initialize(&CONFIG);
+    lazy_static::initialize(&REPOSITORY_CHAIN);
+    lazy_static::initialize(&MOUNT_TABLE);
+    service::check_url_signing();
+    if CONFIG.cache.migrate_legacy_layout {
+        tokio::spawn(async {
+            VolumeRepository::new(&CONFIG.cache.volume_root).migrate_legacy_layout().await
+        });
+    }
+    // Held for the lifetime of the server, dropping a watcher stops its watch.
+    let mut _watchers = Vec::new();
+    if CONFIG.filesystem.watch {
+        let mut watched = Vec::new();
+        if CONFIG.repository.chain.contains(&LayerSpec::Filesystem) {
+            watched.push((CONFIG.filesystem.root.as_str(), REPOSITORY_CHAIN.clone()));
+        }
+        for mount in MOUNT_TABLE.mounts() {
+            if let Some(OriginSpec::Filesystem(root)) = &mount.origin {
+                watched.push((root.as_str(), mount.chain.clone()));
+            }
+        }
+        for (root, chain) in watched {
+            match watch_for_changes(Path::new(root), chain) {
+                Ok(watcher) => _watchers.push(watcher),
+                Err(e) => error!("Could not watch {root} for changes: {e}"),
+            }
+        }
+    }
+
     let addr = SocketAddr::from(([0, 0, 0, 0], 8080));
 
     info!("Attempting to start server at {addr}");
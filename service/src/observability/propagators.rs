@@ -2,6 +2,7 @@ use hyper::header::HeaderName;
 use hyper::HeaderMap;
 use opentelemetry::propagation::{Extractor, Injector};
 
+#[allow(dead_code)]
 pub struct HyperHeaderInjector<'a>(pub &'a mut HeaderMap);
 
 impl<'a> Injector for HyperHeaderInjector<'a> {
@@ -0,0 +1,154 @@
+use crate::domain::image_item::ImageItem;
+use crate::domain::status::CacheWriteCounts;
+use crate::repository::ImageRepository;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TrySendError;
+use tracing::{debug, warn};
+
+type CacheWrite = (Arc<dyn ImageRepository>, String, ImageItem);
+
+/// Populates caches off the request path through a bounded queue.
+/// A full queue drops the write, the next request for the image will try again.
+#[derive(Debug)]
+pub struct CacheWriter {
+    sender: mpsc::Sender<CacheWrite>,
+    stats: Arc<CacheWriterStats>,
+}
+
+#[derive(Debug, Default)]
+struct CacheWriterStats {
+    written: AtomicU64,
+    failed: AtomicU64,
+    dropped: AtomicU64,
+}
+
+impl CacheWriter {
+    /// Start the writer task, must be called from within the Tokio runtime.
+    pub fn new(queue_depth: usize) -> Self {
+        let (sender, receiver) = mpsc::channel(queue_depth.max(1));
+        let stats = Arc::new(CacheWriterStats::default());
+        tokio::spawn(run(receiver, stats.clone()));
+        CacheWriter { sender, stats }
+    }
+
+    /// Queue an image to be written to `cache` without waiting for it.
+    pub fn enqueue(&self, cache: Arc<dyn ImageRepository>, path: &str, item: ImageItem) {
+        match self.sender.try_send((cache, path.to_string(), item)) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                let dropped = self.stats.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+                warn!("Cache write queue full, dropped write of {path} ({dropped} dropped so far)");
+            }
+            Err(TrySendError::Closed(_)) => {
+                warn!("Cache writer stopped, dropped write of {path}");
+            }
+        }
+    }
+
+    /// Writes done, failed and dropped since the writer started.
+    pub fn counts(&self) -> CacheWriteCounts {
+        CacheWriteCounts {
+            written: self.stats.written.load(Ordering::Relaxed),
+            failed: self.stats.failed.load(Ordering::Relaxed),
+            dropped: self.stats.dropped.load(Ordering::Relaxed),
+        }
+    }
+}
+
+async fn run(mut receiver: mpsc::Receiver<CacheWrite>, stats: Arc<CacheWriterStats>) {
+    while let Some((cache, path, item)) = receiver.recv().await {
+        match cache.write_image(&path, &item).await {
+            Ok(()) => {
+                stats.written.fetch_add(1, Ordering::Relaxed);
+                debug!("Cached image at {path} in {}", cache.name());
+            }
+            Err(_) => {
+                let failed = stats.failed.fetch_add(1, Ordering::Relaxed) + 1;
+                warn!("Could not cache image at {path} in {} ({failed} failed so far)", cache.name());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::CacheConfig;
+    use crate::domain::error::ErrorResponse;
+    use crate::domain::error::ErrorResponse::{ImageNotFoundInCacheError, ImageWriteError};
+    use crate::domain::image_item::OriginMetadata;
+    use crate::repository::chain::RepositoryChain;
+    use crate::repository::memory_repository::MemoryRepository;
+    use async_trait::async_trait;
+    use hyper::body::Bytes;
+    use std::time::Duration;
+
+    /// Cache whose writes always fail.
+    #[derive(Debug)]
+    struct FailingCache;
+
+    #[async_trait]
+    impl ImageRepository for FailingCache {
+        fn name(&self) -> &'static str {
+            "failing"
+        }
+
+        async fn read_image(&self, _path: &str) -> Result<ImageItem, ErrorResponse> {
+            Err(ImageNotFoundInCacheError {})
+        }
+
+        fn is_cache(&self) -> bool {
+            true
+        }
+
+        async fn write_image(&self, _path: &str, _item: &ImageItem) -> Result<(), ErrorResponse> {
+            Err(ImageWriteError {})
+        }
+    }
+
+    fn item() -> ImageItem {
+        ImageItem {
+            bytes: Bytes::from_static(b"image"),
+            metadata: OriginMetadata::new(None, None, None),
+        }
+    }
+
+    async fn settle(writer: &CacheWriter, done: impl Fn(CacheWriteCounts) -> bool) -> CacheWriteCounts {
+        for _ in 0..100 {
+            if done(writer.counts()) {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        writer.counts()
+    }
+
+    #[tokio::test]
+    async fn full_queue_drops_writes() {
+        let writer = CacheWriter::new(1);
+        let cache: Arc<dyn ImageRepository> = Arc::new(MemoryRepository::new(1024));
+        // The writer task has not run yet on this single threaded runtime.
+        for path in ["/a.jpg", "/b.jpg", "/c.jpg"] {
+            writer.enqueue(cache.clone(), path, item());
+        }
+        assert_eq!(writer.counts().dropped, 2);
+        let counts = settle(&writer, |counts| counts.written == 1).await;
+        assert_eq!(counts, CacheWriteCounts { written: 1, failed: 0, dropped: 2 });
+        assert!(cache.read_image("/a.jpg").await.is_ok())
+    }
+
+    #[tokio::test]
+    async fn failed_writes_are_counted_not_returned() {
+        let failing: Arc<dyn ImageRepository> = Arc::new(FailingCache);
+        let origin: Arc<dyn ImageRepository> = Arc::new(MemoryRepository::new(1024));
+        origin.write_image("/a.jpg", &item()).await.unwrap();
+        let chain = RepositoryChain::new(vec![failing.clone(), origin], &CacheConfig::default());
+        assert_eq!(Arc::new(chain).get_image("/a.jpg").await.unwrap().bytes, "image");
+
+        let writer = CacheWriter::new(4);
+        writer.enqueue(failing, "/a.jpg", item());
+        assert_eq!(settle(&writer, |counts| counts.failed == 1).await.failed, 1)
+    }
+}
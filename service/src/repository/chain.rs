@@ -0,0 +1,398 @@
+use crate::client::bucket_client::BucketClient;
+use crate::client::s3_client::S3Client;
+use crate::client::sigv4::Credentials;
+use crate::config::{CacheConfig, Config};
+use crate::domain::error::ErrorResponse;
+use crate::domain::error::ErrorResponse::{ImageNotFoundError, UploadNotSupportedError};
+use crate::domain::image_item::{ImageItem, OriginMetadata};
+use crate::domain::image_path::normalize;
+use crate::domain::purge::{PurgeScope, PurgeSummary};
+use crate::domain::status::{BreakerStatus, CacheWriteCounts};
+use crate::repository::cache_writer::CacheWriter;
+use crate::repository::filesystem_repository::FilesystemRepository;
+use crate::repository::memory_repository::MemoryRepository;
+use crate::repository::negative_cache::NegativeCache;
+use crate::repository::object_repository::ObjectRepository;
+use crate::repository::volume_repository::VolumeRepository;
+use crate::repository::ImageRepository;
+use hyper::body::Bytes;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+use tracing::{debug, info, instrument, warn};
+
+/// A layer of the chain as named in config, e.g. `"memory"`, `"volume"` or `"bucket"`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum LayerSpec {
+    Memory,
+    Volume,
+    Bucket,
+    S3,
+    Filesystem,
+}
+
+impl FromStr for LayerSpec {
+    type Err = String;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        match spec {
+            "memory" => Ok(LayerSpec::Memory),
+            "volume" => Ok(LayerSpec::Volume),
+            "bucket" => Ok(LayerSpec::Bucket),
+            "s3" => Ok(LayerSpec::S3),
+            "filesystem" => Ok(LayerSpec::Filesystem),
+            _ => Err(format!("unknown repository layer \"{spec}\"")),
+        }
+    }
+}
+
+impl TryFrom<String> for LayerSpec {
+    type Error = String;
+
+    fn try_from(spec: String) -> Result<Self, Self::Error> {
+        spec.parse()
+    }
+}
+
+impl Display for LayerSpec {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LayerSpec::Memory => write!(f, "memory"),
+            LayerSpec::Volume => write!(f, "volume"),
+            LayerSpec::Bucket => write!(f, "bucket"),
+            LayerSpec::S3 => write!(f, "s3"),
+            LayerSpec::Filesystem => write!(f, "filesystem"),
+        }
+    }
+}
+
+impl LayerSpec {
+    pub fn build(&self, config: &Config) -> Arc<dyn ImageRepository> {
+        let cache = &config.cache;
+        let negative_cache = || NegativeCache::new(cache.negative_ttl(), cache.negative_capacity);
+        match self {
+            LayerSpec::Memory => Arc::new(MemoryRepository::new(config.memory_layer_bytes())),
+            LayerSpec::Volume => Arc::new(VolumeRepository::new(&cache.volume_root)),
+            LayerSpec::Bucket => Arc::new(ObjectRepository::new(
+                BucketClient::new(config.bucket.clone(), config.origin.clone()),
+                negative_cache(),
+            )),
+            LayerSpec::S3 => Arc::new(ObjectRepository::new(
+                S3Client::new(config.s3.clone(), Credentials::from_env(), config.origin.clone()),
+                negative_cache(),
+            )),
+            LayerSpec::Filesystem => Arc::new(FilesystemRepository::new(&config.filesystem.root, config.origin.max_object_bytes)),
+        }
+    }
+}
+
+/// Ordered layers images are looked up in. A hit is copied into the caches in front of
+/// the layer it came from, and a stale hit from a cache is revalidated against the next
+/// origin behind it.
+#[derive(Debug)]
+pub struct RepositoryChain {
+    layers: Vec<Arc<dyn ImageRepository>>,
+    writer: CacheWriter,
+    revalidate_after: Duration,
+    revalidating: Mutex<HashSet<String>>,
+}
+
+impl RepositoryChain {
+    /// Must be called from within the Tokio runtime as it starts the cache writer.
+    pub fn new(layers: Vec<Arc<dyn ImageRepository>>, cache: &CacheConfig) -> Self {
+        assert!(!layers.is_empty(), "A repository chain needs at least one layer");
+        RepositoryChain {
+            layers,
+            writer: CacheWriter::new(cache.write_queue_depth),
+            revalidate_after: cache.revalidate_after(),
+            revalidating: Mutex::new(HashSet::new()),
+        }
+    }
+
+    pub fn from_specs(specs: &[LayerSpec], config: &Config) -> Self {
+        let layers = specs.iter().map(|spec| spec.build(config)).collect();
+        RepositoryChain::new(layers, &config.cache)
+    }
+
+    /// Every layer sees the path normalized, paths that could escape an origin or the volume
+    /// cache are refused before reaching any of them.
+    #[instrument(skip(self))]
+    pub async fn get_image(self: &Arc<Self>, path: &str) -> Result<ImageItem, ErrorResponse> {
+        let path = &normalize(path)?;
+        let mut last_error = ImageNotFoundError {};
+        for (index, layer) in self.layers.iter().enumerate() {
+            match layer.read_image(path).await {
+                Ok(item) => {
+                    debug!("Found {path} in {}", layer.name());
+                    if layer.is_cache() && item.metadata.is_stale(self.revalidate_after) {
+                        self.spawn_revalidation(path, index, item.metadata.clone());
+                    }
+                    self.populate(&self.layers[..index], path, &item);
+                    return Ok(item);
+                }
+                Err(e) if !layer.is_cache() => last_error = e,
+                Err(_) => {}
+            }
+        }
+        Err(last_error)
+    }
+
+    pub async fn purge(&self, scope: &PurgeScope) -> Result<PurgeSummary, ErrorResponse> {
+        let mut summary = PurgeSummary::default();
+        for layer in &self.layers {
+            summary.record(layer.name(), layer.purge(scope).await?);
+        }
+        Ok(summary)
+    }
+
+    /// Store a new original in the first origin and write it through to the caches in
+    /// front of it, replacing any copies they hold. Returns the name of the origin.
+    #[instrument(skip(self, bytes))]
+    pub async fn store_image(
+        &self,
+        path: &str,
+        bytes: Bytes,
+        content_type: &str,
+    ) -> Result<(&'static str, OriginMetadata), ErrorResponse> {
+        let path = &normalize(path)?;
+        let Some(index) = self.layers.iter().position(|layer| !layer.is_cache()) else {
+            return Err(UploadNotSupportedError {});
+        };
+        let origin = &self.layers[index];
+        let metadata = origin.store_image(path, &bytes, content_type).await?;
+        let item = ImageItem {
+            bytes,
+            metadata: metadata.clone(),
+        };
+        for cache in &self.layers[..index] {
+            if cache.write_image(path, &item).await.is_err() {
+                warn!("Could not warm {} with uploaded {path}", cache.name());
+            }
+        }
+        Ok((origin.name(), metadata))
+    }
+
+    /// Circuit breaker state of each origin in the chain that has one.
+    pub fn breaker_statuses(&self) -> Vec<(&'static str, BreakerStatus)> {
+        self.layers
+            .iter()
+            .filter_map(|layer| layer.breaker_status().map(|status| (layer.name(), status)))
+            .collect()
+    }
+
+    /// Counts of the background writes to the caches of the chain.
+    pub fn cache_write_counts(&self) -> CacheWriteCounts {
+        self.writer.counts()
+    }
+
+    fn populate(&self, layers: &[Arc<dyn ImageRepository>], path: &str, item: &ImageItem) {
+        for cache in layers.iter().filter(|layer| layer.is_cache()) {
+            self.writer.enqueue(cache.clone(), path, item.clone());
+        }
+    }
+
+    /// Revalidate a cached image without holding up the current request,
+    /// at most one revalidation per path is in flight.
+    fn spawn_revalidation(self: &Arc<Self>, path: &str, index: usize, metadata: OriginMetadata) {
+        if !self.layers[index + 1..].iter().any(|layer| !layer.is_cache()) {
+            return;
+        }
+        if !self.revalidating.lock().unwrap().insert(path.to_string()) {
+            return;
+        }
+        let guard = RevalidationGuard {
+            chain: self.clone(),
+            path: path.to_string(),
+        };
+        tokio::spawn(async move {
+            guard.chain.revalidate(&guard.path, index, metadata).await;
+        });
+    }
+
+    #[instrument(skip(self, metadata))]
+    async fn revalidate(&self, path: &str, index: usize, metadata: OriginMetadata) {
+        let Some(origin) = self.layers[index + 1..].iter().find(|layer| !layer.is_cache()) else { return };
+        let caches = &self.layers[..=index];
+        match origin.revalidate_image(path, &metadata).await {
+            Ok(Some(item)) => {
+                debug!("Image changed at origin, refreshing {path}");
+                self.populate(caches, path, &item);
+            }
+            Ok(None) => {
+                let refreshed = metadata.refreshed();
+                for cache in caches.iter().filter(|layer| layer.is_cache()) {
+                    if cache.write_metadata(path, &refreshed).await.is_err() {
+                        warn!("Could not refresh metadata of {path} in {}", cache.name());
+                    }
+                }
+            }
+            Err(ImageNotFoundError {}) => {
+                info!("Image deleted at origin, purging cached {path}");
+                let scope = PurgeScope::Path(path.to_string());
+                for cache in caches.iter().filter(|layer| layer.is_cache()) {
+                    if cache.purge(&scope).await.is_err() {
+                        warn!("Could not purge deleted {path} from {}", cache.name());
+                    }
+                }
+            }
+            Err(_) => warn!("Revalidation failed, serving stale image at {path}"),
+        }
+    }
+}
+
+/// Marks a path as revalidating until dropped, so a revalidation that panics or is
+/// cancelled does not keep the path from ever being revalidated again.
+struct RevalidationGuard {
+    chain: Arc<RepositoryChain>,
+    path: String,
+}
+
+impl Drop for RevalidationGuard {
+    fn drop(&mut self) {
+        let mut revalidating = self.chain.revalidating.lock().unwrap_or_else(PoisonError::into_inner);
+        revalidating.remove(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::error::ErrorResponse::{ImageNotFoundInCacheError, InvalidPathError};
+    use async_trait::async_trait;
+    use hyper::body::Bytes;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Origin serving a fixed image and counting how often it is asked.
+    #[derive(Debug, Default)]
+    struct FakeOrigin {
+        reads: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl ImageRepository for FakeOrigin {
+        fn name(&self) -> &'static str {
+            "fake"
+        }
+
+        async fn read_image(&self, path: &str) -> Result<ImageItem, ErrorResponse> {
+            self.reads.fetch_add(1, Ordering::SeqCst);
+            match path {
+                "/missing.jpg" => Err(ImageNotFoundError {}),
+                _ => Ok(ImageItem {
+                    bytes: Bytes::from_static(b"image"),
+                    metadata: OriginMetadata::new(None, None, None),
+                }),
+            }
+        }
+
+        async fn store_image(&self, _path: &str, _bytes: &Bytes, _content_type: &str) -> Result<OriginMetadata, ErrorResponse> {
+            Ok(OriginMetadata::new(Some("\"stored\"".to_string()), None, None))
+        }
+    }
+
+    fn chain(origin: Arc<FakeOrigin>) -> Arc<RepositoryChain> {
+        let layers: Vec<Arc<dyn ImageRepository>> = vec![Arc::new(MemoryRepository::new(1024)), origin];
+        Arc::new(RepositoryChain::new(layers, &CacheConfig::default()))
+    }
+
+    #[test]
+    fn layer_specs_parse() {
+        assert_eq!("memory".parse::<LayerSpec>().unwrap(), LayerSpec::Memory);
+        assert_eq!(LayerSpec::Bucket.to_string(), "bucket");
+        assert!("carrier-pigeon".parse::<LayerSpec>().is_err())
+    }
+
+    #[tokio::test]
+    async fn chain_populates_caches_in_front_of_hit() {
+        let origin = Arc::new(FakeOrigin::default());
+        let chain = chain(origin.clone());
+        assert_eq!(chain.get_image("/a.jpg").await.unwrap().bytes, "image");
+
+        for _ in 0..100 {
+            if chain.layers[0].read_image("/a.jpg").await.is_ok() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        chain.get_image("/a.jpg").await.unwrap();
+        assert_eq!(origin.reads.load(Ordering::SeqCst), 1)
+    }
+
+    #[tokio::test]
+    async fn chain_stores_in_origin_and_warms_caches() {
+        let origin = Arc::new(FakeOrigin::default());
+        let chain = chain(origin.clone());
+        let (name, metadata) = chain
+            .store_image("/new.jpg", Bytes::from_static(b"new"), "image/jpeg")
+            .await
+            .unwrap();
+        assert_eq!((name, metadata.etag.as_deref()), ("fake", Some("\"stored\"")));
+
+        let cached = chain.layers[0].read_image("/new.jpg").await.unwrap();
+        assert_eq!(cached.bytes, "new");
+        assert_eq!(origin.reads.load(Ordering::SeqCst), 0)
+    }
+
+    #[tokio::test]
+    async fn chain_returns_origin_error_over_cache_miss() {
+        let chain = chain(Arc::new(FakeOrigin::default()));
+        assert!(matches!(chain.get_image("/missing.jpg").await, Err(ImageNotFoundError {})));
+
+        let cache_only: Vec<Arc<dyn ImageRepository>> = vec![Arc::new(MemoryRepository::new(1024))];
+        let cache_only = Arc::new(RepositoryChain::new(cache_only, &CacheConfig::default()));
+        assert!(matches!(cache_only.get_image("/a.jpg").await, Err(ImageNotFoundError {})));
+        assert!(!matches!(cache_only.get_image("/a.jpg").await, Err(ImageNotFoundInCacheError {})))
+    }
+
+    #[tokio::test]
+    async fn chain_purges_copies_deleted_at_origin() {
+        let origin = Arc::new(FakeOrigin::default());
+        let chain = chain(origin.clone());
+        let stale = ImageItem {
+            bytes: Bytes::from_static(b"deleted"),
+            metadata: OriginMetadata { fetched_at: 0, ..OriginMetadata::new(None, None, None) },
+        };
+        chain.layers[0].write_image("/missing.jpg", &stale).await.unwrap();
+        assert_eq!(chain.get_image("/missing.jpg").await.unwrap().bytes, "deleted");
+
+        for _ in 0..100 {
+            if chain.layers[0].read_image("/missing.jpg").await.is_err() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(matches!(chain.get_image("/missing.jpg").await, Err(ImageNotFoundError {})))
+    }
+
+    #[tokio::test]
+    async fn revalidation_guard_releases_path_on_panic() {
+        let chain = chain(Arc::new(FakeOrigin::default()));
+        chain.revalidating.lock().unwrap().insert("/a.jpg".to_string());
+        let guard = RevalidationGuard {
+            chain: chain.clone(),
+            path: "/a.jpg".to_string(),
+        };
+        let task = tokio::spawn(async move {
+            let _guard = guard;
+            panic!("revalidation failed");
+        });
+        assert!(task.await.is_err());
+        assert!(!chain.revalidating.lock().unwrap().contains("/a.jpg"))
+    }
+
+    #[tokio::test]
+    async fn chain_refuses_traversal_before_any_layer() {
+        let origin = Arc::new(FakeOrigin::default());
+        let chain = chain(origin.clone());
+        assert!(matches!(chain.get_image("/a/%2e%2e/%2e%2e/etc/passwd").await, Err(InvalidPathError {})));
+        assert!(matches!(
+            chain.store_image("/..%2fescape.jpg", Bytes::from_static(b"x"), "image/jpeg").await,
+            Err(InvalidPathError {})
+        ));
+        assert_eq!(origin.reads.load(Ordering::SeqCst), 0)
+    }
+}
@@ -0,0 +1,211 @@
+use crate::domain::error::ErrorResponse;
+use crate::domain::error::ErrorResponse::{ImageNotFoundError, PayloadTooLargeError};
+use crate::domain::image_item::{ImageItem, OriginMetadata};
+use crate::domain::image_path::decoded_segments;
+use crate::domain::purge::PurgeScope;
+use crate::repository::chain::RepositoryChain;
+use crate::repository::ImageRepository;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use hyper::body::Bytes;
+use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
+use serde::Deserialize;
+use std::fs::Metadata;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+use tracing::{debug, error, instrument, warn};
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct FilesystemConfig {
+    /// Directory holding the originals, request paths are resolved relative to it.
+    pub root: String,
+    /// Purge cached copies of files as they change on disk.
+    pub watch: bool,
+}
+
+impl Default for FilesystemConfig {
+    fn default() -> Self {
+        FilesystemConfig {
+            root: "/mnt/gcsfuse".to_string(),
+            watch: false,
+        }
+    }
+}
+
+/// Read-only origin serving originals from a local directory.
+#[derive(Debug)]
+pub struct FilesystemRepository {
+    root: PathBuf,
+    max_bytes: u64,
+}
+
+impl FilesystemRepository {
+    /// Files larger than `max_bytes` are refused rather than read.
+    pub fn new(root: impl Into<PathBuf>, max_bytes: u64) -> Self {
+        FilesystemRepository {
+            root: root.into(),
+            max_bytes,
+        }
+    }
+
+    /// Map a request path onto a file under the root, `None` for paths that would leave
+    /// it, either through `..` segments or through a symlink pointing elsewhere.
+    async fn resolve(&self, path: &str) -> Option<PathBuf> {
+        let mut resolved = self.root.clone();
+        for segment in decoded_segments(path).ok()? {
+            resolved.push(segment.as_ref());
+        }
+        let root = tokio::fs::canonicalize(&self.root).await.ok()?;
+        let canonical = tokio::fs::canonicalize(&resolved).await.ok()?;
+        canonical.starts_with(&root).then_some(canonical)
+    }
+
+    async fn stat(&self, path: &str) -> Result<(PathBuf, OriginMetadata), ErrorResponse> {
+        let Some(file_path) = self.resolve(path).await else {
+            debug!("No file under the root for {path}");
+            return Err(ImageNotFoundError {});
+        };
+        match tokio::fs::metadata(&file_path).await {
+            Ok(metadata) if metadata.is_file() && metadata.len() > self.max_bytes => {
+                warn!("{} is {} bytes, over the limit of {}", file_path.display(), metadata.len(), self.max_bytes);
+                Err(PayloadTooLargeError {})
+            }
+            Ok(metadata) if metadata.is_file() => Ok((file_path, origin_metadata(&metadata))),
+            _ => Err(ImageNotFoundError {}),
+        }
+    }
+}
+
+#[async_trait]
+impl ImageRepository for FilesystemRepository {
+    fn name(&self) -> &'static str {
+        "filesystem"
+    }
+
+    #[instrument(skip(self))]
+    async fn read_image(&self, path: &str) -> Result<ImageItem, ErrorResponse> {
+        let (file_path, metadata) = self.stat(path).await?;
+        match tokio::fs::read(&file_path).await {
+            Ok(bytes) => Ok(ImageItem {
+                bytes: Bytes::from(bytes),
+                metadata,
+            }),
+            Err(e) => {
+                error!("Could not read {}: {e}", file_path.display());
+                Err(ImageNotFoundError {})
+            }
+        }
+    }
+
+    /// Compare size and modification time rather than re-reading the file.
+    #[instrument(skip(self, metadata))]
+    async fn revalidate_image(
+        &self,
+        path: &str,
+        metadata: &OriginMetadata,
+    ) -> Result<Option<ImageItem>, ErrorResponse> {
+        let (_, current) = self.stat(path).await?;
+        if current.etag.is_some() && current.etag == metadata.etag {
+            return Ok(None);
+        }
+        self.read_image(path).await.map(Some)
+    }
+}
+
+/// Weak validators derived from the file's size and modification time.
+fn origin_metadata(metadata: &Metadata) -> OriginMetadata {
+    let modified = metadata.modified().ok();
+    let etag = modified
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map(|since_epoch| format!("W/\"{:x}-{:x}\"", metadata.len(), since_epoch.as_nanos()));
+    let last_modified = modified.map(|time| {
+        DateTime::<Utc>::from(time)
+            .format("%a, %d %b %Y %H:%M:%S GMT")
+            .to_string()
+    });
+    OriginMetadata::new(etag, last_modified, None)
+}
+
+/// Purge every layer of `chain` of a file as soon as it changes under `root`, for as long
+/// as the returned watcher is held. Cached copies are keyed by request path, so copies
+/// requested through a percent-encoded path are left to revalidation.
+pub fn watch_for_changes(root: &Path, chain: Arc<RepositoryChain>) -> notify::Result<RecommendedWatcher> {
+    let root = root.canonicalize()?;
+    let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel::<PathBuf>();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<Event>| match event {
+        Ok(event) if !event.kind.is_access() => {
+            for path in event.paths {
+                let _ = sender.send(path);
+            }
+        }
+        Ok(_) => {}
+        Err(e) => warn!("Error watching originals: {e}"),
+    })?;
+    watcher.watch(&root, RecursiveMode::Recursive)?;
+
+    tokio::spawn(async move {
+        while let Some(file_path) = receiver.recv().await {
+            let Some(key) = request_path(&root, &file_path) else { continue };
+            debug!("{key} changed on disk, purging cached copies");
+            if chain.purge(&PurgeScope::Path(key.clone())).await.is_err() {
+                warn!("Could not purge cached copies of {key}");
+            }
+        }
+    });
+    Ok(watcher)
+}
+
+fn request_path(root: &Path, file_path: &Path) -> Option<String> {
+    let relative = file_path.strip_prefix(root).ok()?;
+    let segments: Vec<&str> = relative.iter().map(|segment| segment.to_str()).collect::<Option<_>>()?;
+    Some(format!("/{}", segments.join("/")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn repository() -> (tempfile::TempDir, FilesystemRepository) {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("root/photos")).unwrap();
+        std::fs::write(dir.path().join("root/photos/a b.jpg"), b"image").unwrap();
+        std::fs::write(dir.path().join("secret.txt"), b"secret").unwrap();
+        let repository = FilesystemRepository::new(dir.path().join("root"), 16);
+        (dir, repository)
+    }
+
+    #[tokio::test]
+    async fn filesystem_reads_under_root() {
+        let (_dir, repository) = repository();
+        let item = repository.read_image("/photos/a%20b.jpg").await.unwrap();
+        assert_eq!(item.bytes, "image");
+        assert!(repository.revalidate_image("/photos/a b.jpg", &item.metadata).await.unwrap().is_none())
+    }
+
+    #[tokio::test]
+    async fn filesystem_rejects_traversal() {
+        let (dir, repository) = repository();
+        std::os::unix::fs::symlink(dir.path().join("secret.txt"), dir.path().join("root/link.txt")).unwrap();
+
+        for path in ["/../secret.txt", "/photos/%2e%2e/%2E%2E/secret.txt", "/photos%2f..%2f..%2fsecret.txt", "/link.txt"] {
+            assert!(repository.read_image(path).await.is_err(), "{path} escaped the root");
+        }
+        assert!(repository.read_image("/photos").await.is_err())
+    }
+
+    #[tokio::test]
+    async fn filesystem_refuses_large_files() {
+        let (dir, repository) = repository();
+        std::fs::write(dir.path().join("root/large.tif"), [0u8; 17]).unwrap();
+        assert!(matches!(repository.read_image("/large.tif").await, Err(PayloadTooLargeError {})))
+    }
+
+    #[test]
+    fn request_path_is_relative_to_root() {
+        let path = request_path(Path::new("/srv/images"), Path::new("/srv/images/a/b.jpg"));
+        assert_eq!(path.as_deref(), Some("/a/b.jpg"));
+        assert!(request_path(Path::new("/srv/images"), Path::new("/etc/passwd")).is_none())
+    }
+}
@@ -0,0 +1,155 @@
+use crate::domain::error::ErrorResponse;
+use crate::domain::error::ErrorResponse::ImageNotFoundInCacheError;
+use crate::domain::image_item::{ImageItem, OriginMetadata};
+use crate::domain::purge::{PurgeReport, PurgeScope};
+use crate::repository::ImageRepository;
+use async_trait::async_trait;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use tracing::instrument;
+
+/// In-process LRU cache of originals, bounded by the total size of the held images.
+#[derive(Debug)]
+pub struct MemoryRepository {
+    max_bytes: u64,
+    entries: Mutex<MemoryEntries>,
+}
+
+#[derive(Debug, Default)]
+struct MemoryEntries {
+    items: HashMap<String, (ImageItem, u64)>,
+    /// Last use of each entry, the lowest tick is evicted first.
+    recency: BTreeMap<u64, String>,
+    tick: u64,
+    bytes: u64,
+}
+
+impl MemoryEntries {
+    fn touch(&mut self, path: &str) -> Option<ImageItem> {
+        self.tick += 1;
+        let tick = self.tick;
+        let (item, last_used) = self.items.get_mut(path)?;
+        self.recency.remove(last_used);
+        *last_used = tick;
+        self.recency.insert(tick, path.to_string());
+        Some(item.clone())
+    }
+
+    fn remove(&mut self, path: &str) -> Option<u64> {
+        let (item, last_used) = self.items.remove(path)?;
+        self.recency.remove(&last_used);
+        let size = item.bytes.len() as u64;
+        self.bytes -= size;
+        Some(size)
+    }
+}
+
+impl MemoryRepository {
+    pub fn new(max_bytes: u64) -> Self {
+        MemoryRepository {
+            max_bytes,
+            entries: Mutex::new(MemoryEntries::default()),
+        }
+    }
+}
+
+#[async_trait]
+impl ImageRepository for MemoryRepository {
+    fn name(&self) -> &'static str {
+        "memory"
+    }
+
+    fn is_cache(&self) -> bool {
+        true
+    }
+
+    async fn read_image(&self, path: &str) -> Result<ImageItem, ErrorResponse> {
+        self.entries
+            .lock()
+            .unwrap()
+            .touch(path)
+            .ok_or(ImageNotFoundInCacheError {})
+    }
+
+    #[instrument(skip(item))]
+    async fn write_image(&self, path: &str, item: &ImageItem) -> Result<(), ErrorResponse> {
+        let size = item.bytes.len() as u64;
+        if size > self.max_bytes {
+            return Ok(());
+        }
+        let mut entries = self.entries.lock().unwrap();
+        entries.remove(path);
+        while entries.bytes + size > self.max_bytes {
+            let Some((_, oldest)) = entries.recency.pop_first() else { break };
+            entries.remove(&oldest);
+        }
+        entries.tick += 1;
+        let tick = entries.tick;
+        entries.items.insert(path.to_string(), (item.clone(), tick));
+        entries.recency.insert(tick, path.to_string());
+        entries.bytes += size;
+        Ok(())
+    }
+
+    async fn write_metadata(&self, path: &str, metadata: &OriginMetadata) -> Result<(), ErrorResponse> {
+        if let Some((item, _)) = self.entries.lock().unwrap().items.get_mut(path) {
+            item.metadata = metadata.clone();
+        }
+        Ok(())
+    }
+
+    async fn purge(&self, scope: &PurgeScope) -> Result<PurgeReport, ErrorResponse> {
+        let mut entries = self.entries.lock().unwrap();
+        let matching: Vec<String> = entries
+            .items
+            .keys()
+            .filter(|path| scope.matches(path))
+            .cloned()
+            .collect();
+        let mut report = PurgeReport::default();
+        for path in matching {
+            if let Some(size) = entries.remove(&path) {
+                report.entries += 1;
+                report.bytes += size;
+            }
+        }
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::body::Bytes;
+
+    fn item(bytes: &'static [u8]) -> ImageItem {
+        ImageItem {
+            bytes: Bytes::from_static(bytes),
+            metadata: OriginMetadata::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn memory_evicts_least_recently_used() {
+        let repository = MemoryRepository::new(8);
+        repository.write_image("/a", &item(b"aaa")).await.unwrap();
+        repository.write_image("/b", &item(b"bbb")).await.unwrap();
+        repository.read_image("/a").await.unwrap();
+        repository.write_image("/c", &item(b"ccc")).await.unwrap();
+
+        assert!(repository.read_image("/a").await.is_ok());
+        assert!(repository.read_image("/b").await.is_err());
+        assert!(repository.read_image("/c").await.is_ok())
+    }
+
+    #[tokio::test]
+    async fn memory_purges_scope() {
+        let repository = MemoryRepository::new(64);
+        repository.write_image("/a/1", &item(b"one")).await.unwrap();
+        repository.write_image("/b/1", &item(b"three")).await.unwrap();
+
+        let report = repository.purge(&PurgeScope::Prefix("/a/".to_string())).await.unwrap();
+        assert_eq!(report, PurgeReport { entries: 1, bytes: 3 });
+        assert!(repository.read_image("/b/1").await.is_ok())
+    }
+}
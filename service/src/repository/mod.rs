@@ -1,8 +1,64 @@
 use crate::domain::error::ErrorResponse;
+use crate::domain::error::ErrorResponse::UploadNotSupportedError;
+use crate::domain::image_item::{ImageItem, OriginMetadata};
+use crate::domain::purge::{PurgeReport, PurgeScope};
+use crate::domain::status::BreakerStatus;
+use async_trait::async_trait;
+use hyper::body::Bytes;
+use std::fmt::Debug;
 
-pub(crate) mod bucket_repository;
+pub(crate) mod cache_writer;
+pub(crate) mod chain;
+pub(crate) mod filesystem_repository;
+pub(crate) mod memory_repository;
+pub(crate) mod mount;
+pub(crate) mod negative_cache;
+pub(crate) mod object_repository;
 pub(crate) mod volume_repository;
 
-pub trait ImageRepository {
-    async fn read_image(&self, path: &str) -> Result<Vec<u8>, ErrorResponse>;
+/// A layer of a `RepositoryChain`. Origins only need `read_image`, caches also store
+/// what the layers behind them returned.
+#[async_trait]
+pub trait ImageRepository: Debug + Send + Sync {
+    /// Name of the layer in logs and purge reports.
+    fn name(&self) -> &'static str;
+
+    async fn read_image(&self, path: &str) -> Result<ImageItem, ErrorResponse>;
+
+    /// Whether the layer holds copies of images from layers behind it.
+    fn is_cache(&self) -> bool {
+        false
+    }
+
+    async fn write_image(&self, _path: &str, _item: &ImageItem) -> Result<(), ErrorResponse> {
+        Ok(())
+    }
+
+    /// Replace the origin metadata of a held copy after the origin confirmed it is current.
+    async fn write_metadata(&self, _path: &str, _metadata: &OriginMetadata) -> Result<(), ErrorResponse> {
+        Ok(())
+    }
+
+    /// Re-request an image from an origin, `None` means the copy described by `metadata` is current.
+    async fn revalidate_image(
+        &self,
+        path: &str,
+        _metadata: &OriginMetadata,
+    ) -> Result<Option<ImageItem>, ErrorResponse> {
+        self.read_image(path).await.map(Some)
+    }
+
+    /// Store a new original in an origin, returning its validators.
+    async fn store_image(&self, _path: &str, _bytes: &Bytes, _content_type: &str) -> Result<OriginMetadata, ErrorResponse> {
+        Err(UploadNotSupportedError {})
+    }
+
+    async fn purge(&self, _scope: &PurgeScope) -> Result<PurgeReport, ErrorResponse> {
+        Ok(PurgeReport::default())
+    }
+
+    /// Circuit breaker state of origins reached over the network.
+    fn breaker_status(&self) -> Option<BreakerStatus> {
+        None
+    }
 }
@@ -0,0 +1,293 @@
+use crate::client::bucket_client::{BucketClient, BucketConfig};
+use crate::client::s3_client::{S3Client, S3Config};
+use crate::client::sigv4::Credentials;
+use crate::config::Config;
+use crate::domain::error::ErrorResponse;
+use crate::domain::purge::{PurgeScope, PurgeSummary};
+use crate::domain::status::{CacheWriteStatus, OriginStatus};
+use crate::domain::transform_policy::TransformPolicy;
+use crate::repository::chain::{LayerSpec, RepositoryChain};
+use crate::repository::filesystem_repository::FilesystemRepository;
+use crate::repository::negative_cache::NegativeCache;
+use crate::repository::object_repository::ObjectRepository;
+use crate::repository::volume_repository::{VolumeRepository, MOUNTS_DIR};
+use crate::repository::ImageRepository;
+use serde::Deserialize;
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// Origin of a mount as named in config, `gcs:<bucket>`, `s3:<bucket>` or `fs:<directory>`.
+/// Buckets are reached with the endpoint and credentials of the `[bucket]` and `[s3]` sections.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum OriginSpec {
+    Gcs(String),
+    S3(String),
+    Filesystem(String),
+}
+
+impl FromStr for OriginSpec {
+    type Err = String;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        match spec.split_once(':') {
+            Some(("gcs", bucket)) if !bucket.is_empty() => Ok(OriginSpec::Gcs(bucket.to_string())),
+            Some(("s3", bucket)) if !bucket.is_empty() => Ok(OriginSpec::S3(bucket.to_string())),
+            Some(("fs", root)) if root.starts_with('/') => Ok(OriginSpec::Filesystem(root.to_string())),
+            _ => Err(format!("unknown mount origin \"{spec}\"")),
+        }
+    }
+}
+
+impl TryFrom<String> for OriginSpec {
+    type Error = String;
+
+    fn try_from(spec: String) -> Result<Self, Self::Error> {
+        spec.parse()
+    }
+}
+
+impl Display for OriginSpec {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OriginSpec::Gcs(bucket) => write!(f, "gcs:{bucket}"),
+            OriginSpec::S3(bucket) => write!(f, "s3:{bucket}"),
+            OriginSpec::Filesystem(root) => write!(f, "fs:{root}"),
+        }
+    }
+}
+
+impl OriginSpec {
+    fn build(&self, config: &Config) -> Arc<dyn ImageRepository> {
+        let negative_cache = NegativeCache::new(config.cache.negative_ttl(), config.cache.negative_capacity);
+        match self {
+            OriginSpec::Gcs(bucket) => Arc::new(ObjectRepository::new(
+                BucketClient::new(
+                    BucketConfig {
+                        bucket: bucket.clone(),
+                        ..config.bucket.clone()
+                    },
+                    config.origin.clone(),
+                ),
+                negative_cache,
+            )),
+            OriginSpec::S3(bucket) => Arc::new(ObjectRepository::new(
+                S3Client::new(
+                    S3Config {
+                        bucket: bucket.clone(),
+                        ..config.s3.clone()
+                    },
+                    Credentials::from_env(),
+                    config.origin.clone(),
+                ),
+                negative_cache,
+            )),
+            OriginSpec::Filesystem(root) => Arc::new(FilesystemRepository::new(root, config.origin.max_object_bytes)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct MountConfig {
+    /// Path prefix served by the mount, `/portfolio/` or `/portfolio/*`.
+    pub prefix: String,
+    pub origin: OriginSpec,
+    /// Cache layers in front of the origin, `"memory"` and `"volume"`.
+    #[serde(default = "default_mount_caches")]
+    pub caches: Vec<LayerSpec>,
+    /// Directory of the mount's volume cache entries, the prefix without slashes by default.
+    pub namespace: Option<String>,
+    #[serde(default)]
+    pub transform: TransformPolicy,
+}
+
+fn default_mount_caches() -> Vec<LayerSpec> {
+    vec![LayerSpec::Volume]
+}
+
+impl MountConfig {
+    /// The prefix as matched against request paths, always ending with a `/`.
+    fn normalized_prefix(&self) -> String {
+        let prefix = self.prefix.trim_end_matches('*').trim_end_matches('/');
+        format!("{prefix}/")
+    }
+
+    fn namespace(&self) -> String {
+        self.namespace
+            .clone()
+            .unwrap_or_else(|| self.normalized_prefix().trim_matches('/').replace('/', "-"))
+    }
+}
+
+/// Paths under `prefix` are looked up in `chain` with the prefix stripped, so
+/// `/portfolio/a.jpg` is the object `/a.jpg` of the mount's origin.
+#[derive(Debug)]
+pub struct Mount {
+    pub prefix: String,
+    /// `None` for the default chain, which is built from `[repository]` instead.
+    pub origin: Option<OriginSpec>,
+    pub chain: Arc<RepositoryChain>,
+    pub transform: TransformPolicy,
+}
+
+impl Mount {
+    /// Panics on an invalid mount, like `Config::load` does for an invalid file.
+    pub fn from_config(mount: &MountConfig, config: &Config) -> Mount {
+        let prefix = mount.normalized_prefix();
+        assert!(prefix.starts_with('/'), "Mount prefix {} must start with /", mount.prefix);
+        let namespace = mount.namespace();
+        assert!(
+            !namespace.trim_matches('.').is_empty() && !namespace.contains(['/', '\\']),
+            "Mount {prefix} needs a namespace usable as a directory name"
+        );
+        let mut layers: Vec<Arc<dyn ImageRepository>> = mount
+            .caches
+            .iter()
+            .map(|cache| match cache {
+                LayerSpec::Volume => Arc::new(VolumeRepository::new(
+                    Path::new(&config.cache.volume_root).join(MOUNTS_DIR).join(&namespace),
+                )) as Arc<dyn ImageRepository>,
+                LayerSpec::Memory => cache.build(config),
+                _ => panic!("Mount {prefix} can only have memory and volume caches, not {cache}"),
+            })
+            .collect();
+        layers.push(mount.origin.build(config));
+        Mount {
+            prefix,
+            origin: Some(mount.origin.clone()),
+            chain: Arc::new(RepositoryChain::new(layers, &config.cache)),
+            transform: mount.transform.clone(),
+        }
+    }
+
+    /// Path of a request within the mount, `None` if the mount does not serve it.
+    pub fn mount_path(&self, path: &str) -> Option<String> {
+        path.strip_prefix(self.prefix.as_str()).map(|rest| format!("/{rest}"))
+    }
+}
+
+/// Mounts by path prefix, the longest matching prefix wins and paths under no mount
+/// fall through to the default chain.
+#[derive(Debug)]
+pub struct MountTable {
+    mounts: Vec<Mount>,
+    default: Mount,
+}
+
+impl MountTable {
+    pub fn new(mut mounts: Vec<Mount>, default_chain: Arc<RepositoryChain>) -> Self {
+        mounts.sort_by_key(|mount| std::cmp::Reverse(mount.prefix.len()));
+        MountTable {
+            mounts,
+            default: Mount {
+                prefix: "/".to_string(),
+                origin: None,
+                chain: default_chain,
+                transform: TransformPolicy::default(),
+            },
+        }
+    }
+
+    pub fn from_config(config: &Config, default_chain: Arc<RepositoryChain>) -> Self {
+        let mounts = config.mounts.iter().map(|mount| Mount::from_config(mount, config)).collect();
+        MountTable::new(mounts, default_chain)
+    }
+
+    /// The mount serving `path` and the path within it.
+    pub fn resolve(&self, path: &str) -> (&Mount, String) {
+        self.mounts
+            .iter()
+            .find_map(|mount| mount.mount_path(path).map(|mount_path| (mount, mount_path)))
+            .unwrap_or((&self.default, path.to_string()))
+    }
+
+    /// Purge every mount of the entries within `scope`, which is given in request paths.
+    pub async fn purge(&self, scope: &PurgeScope) -> Result<PurgeSummary, ErrorResponse> {
+        let mut summary = self.default.chain.purge(scope).await?;
+        for mount in &self.mounts {
+            let Some(mount_scope) = scope.within(&mount.prefix) else { continue };
+            for (cache, report) in mount.chain.purge(&mount_scope).await?.caches {
+                summary.record(cache, report);
+            }
+        }
+        Ok(summary)
+    }
+
+    /// Circuit breaker state of every origin, the default chain's first.
+    pub fn origin_statuses(&self) -> Vec<OriginStatus> {
+        std::iter::once(&self.default)
+            .chain(&self.mounts)
+            .flat_map(|mount| {
+                mount.chain.breaker_statuses().into_iter().map(|(origin, breaker)| OriginStatus {
+                    mount: mount.prefix.clone(),
+                    origin,
+                    breaker,
+                })
+            })
+            .collect()
+    }
+
+    /// Background cache write counts of every mount, the default chain's first.
+    pub fn cache_write_statuses(&self) -> Vec<CacheWriteStatus> {
+        std::iter::once(&self.default)
+            .chain(&self.mounts)
+            .map(|mount| CacheWriteStatus {
+                mount: mount.prefix.clone(),
+                counts: mount.chain.cache_write_counts(),
+            })
+            .collect()
+    }
+
+    /// The configured mounts, without the default chain.
+    pub fn mounts(&self) -> &[Mount] {
+        &self.mounts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mount_config(prefix: &str, origin: &str) -> MountConfig {
+        MountConfig {
+            prefix: prefix.to_string(),
+            origin: origin.parse().unwrap(),
+            caches: vec![LayerSpec::Memory],
+            namespace: None,
+            transform: TransformPolicy::default(),
+        }
+    }
+
+    #[test]
+    fn origin_specs_parse() {
+        assert_eq!("gcs:bucket-a".parse::<OriginSpec>().unwrap(), OriginSpec::Gcs("bucket-a".to_string()));
+        assert_eq!("fs:/srv/img".parse::<OriginSpec>().unwrap().to_string(), "fs:/srv/img");
+        assert!("fs:relative".parse::<OriginSpec>().is_err());
+        assert!("gcs:".parse::<OriginSpec>().is_err())
+    }
+
+    #[tokio::test]
+    async fn mount_table_resolves_longest_prefix() {
+        let config = Config::default();
+        let mounts = vec![
+            Mount::from_config(&mount_config("/shop/*", "fs:/srv/shop"), &config),
+            Mount::from_config(&mount_config("/shop/outlet/", "fs:/srv/outlet"), &config),
+        ];
+        let default_chain = Arc::new(RepositoryChain::from_specs(&[LayerSpec::Memory], &config));
+        let table = MountTable::new(mounts, default_chain);
+
+        let (mount, path) = table.resolve("/shop/outlet/a.jpg");
+        assert_eq!((mount.prefix.as_str(), path.as_str()), ("/shop/outlet/", "/a.jpg"));
+        let (mount, path) = table.resolve("/shop/b.jpg");
+        assert_eq!((mount.prefix.as_str(), path.as_str()), ("/shop/", "/b.jpg"));
+        let (mount, path) = table.resolve("/shopping/c.jpg");
+        assert_eq!((mount.prefix.as_str(), path.as_str()), ("/", "/shopping/c.jpg"))
+    }
+
+    #[test]
+    fn mount_namespace_defaults_to_prefix() {
+        assert_eq!(mount_config("/sites/shop/*", "s3:shop").namespace(), "sites-shop")
+    }
+}
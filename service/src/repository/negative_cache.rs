@@ -0,0 +1,139 @@
+use crate::domain::purge::{PurgeReport, PurgeScope};
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Short-lived record of paths the origin confirmed do not exist.
+#[derive(Debug)]
+pub struct NegativeCache {
+    ttl: Duration,
+    capacity: usize,
+    entries: Mutex<NegativeEntries>,
+}
+
+#[derive(Debug, Default)]
+struct NegativeEntries {
+    expiries: HashMap<String, Instant>,
+    /// Insertion order, which with a fixed TTL is also expiry order.
+    order: VecDeque<(String, Instant)>,
+}
+
+impl NegativeEntries {
+    /// Drop entries from the front of the queue while `evict` says so.
+    fn evict_while(&mut self, evict: impl Fn(&Self, Instant) -> bool) {
+        while let Some((path, expiry)) = self.order.front() {
+            if !evict(self, *expiry) {
+                break;
+            }
+            if self.expiries.get(path) == Some(expiry) {
+                self.expiries.remove(path);
+            }
+            self.order.pop_front();
+        }
+    }
+}
+
+impl NegativeCache {
+    pub fn new(ttl: Duration, capacity: usize) -> Self {
+        NegativeCache {
+            ttl,
+            capacity,
+            entries: Mutex::new(NegativeEntries::default()),
+        }
+    }
+
+    pub fn contains(&self, path: &str) -> bool {
+        let entries = self.entries.lock().unwrap();
+        entries
+            .expiries
+            .get(path)
+            .is_some_and(|expiry| *expiry > Instant::now())
+    }
+
+    pub fn insert(&self, path: &str) {
+        if self.capacity == 0 || self.ttl.is_zero() {
+            return;
+        }
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+        entries.evict_while(|_, expiry| expiry <= now);
+        // A path already held is refreshed, which must not evict any other.
+        if !entries.expiries.contains_key(path) {
+            entries.evict_while(|entries, _| entries.expiries.len() >= self.capacity);
+        }
+        let expiry = now + self.ttl;
+        entries.expiries.insert(path.to_string(), expiry);
+        entries.order.push_back((path.to_string(), expiry));
+        // Refreshing leaves the earlier queue entry behind, drop those once they pile up.
+        if entries.order.len() > 2 * self.capacity {
+            let NegativeEntries { expiries, order } = &mut *entries;
+            order.retain(|(path, expiry)| expiries.get(path) == Some(expiry));
+        }
+    }
+
+    pub fn purge(&self, scope: &PurgeScope) -> PurgeReport {
+        let mut entries = self.entries.lock().unwrap();
+        let before = entries.expiries.len();
+        entries.expiries.retain(|path, _| !scope.matches(path));
+        let NegativeEntries { expiries, order } = &mut *entries;
+        order.retain(|(path, _)| expiries.contains_key(path));
+        PurgeReport {
+            entries: (before - entries.expiries.len()) as u64,
+            bytes: 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn negative_cache_expires_entries() {
+        let cache = NegativeCache::new(Duration::from_millis(20), 10);
+        cache.insert("/a.jpg");
+        assert!(cache.contains("/a.jpg"));
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(!cache.contains("/a.jpg"))
+    }
+
+    #[test]
+    fn negative_cache_respects_capacity() {
+        let cache = NegativeCache::new(Duration::from_secs(60), 2);
+        cache.insert("/a.jpg");
+        cache.insert("/b.jpg");
+        cache.insert("/c.jpg");
+        assert!(!cache.contains("/a.jpg"));
+        assert!(cache.contains("/b.jpg"));
+        assert!(cache.contains("/c.jpg"))
+    }
+
+    #[test]
+    fn negative_cache_refreshes_held_paths() {
+        let cache = NegativeCache::new(Duration::from_secs(60), 2);
+        cache.insert("/a.jpg");
+        cache.insert("/b.jpg");
+        for _ in 0..10 {
+            cache.insert("/b.jpg");
+        }
+        assert!(cache.contains("/a.jpg"));
+        assert!(cache.contains("/b.jpg"));
+        assert!(cache.entries.lock().unwrap().order.len() <= 4);
+
+        cache.insert("/c.jpg");
+        assert!(!cache.contains("/a.jpg"));
+        assert!(cache.contains("/b.jpg"))
+    }
+
+    #[test]
+    fn negative_cache_purges_scope() {
+        let cache = NegativeCache::new(Duration::from_secs(60), 10);
+        cache.insert("/a/1.jpg");
+        cache.insert("/a/2.jpg");
+        cache.insert("/b/1.jpg");
+        let report = cache.purge(&PurgeScope::Prefix("/a/".to_string()));
+        assert_eq!(report.entries, 2);
+        assert!(!cache.contains("/a/1.jpg"));
+        assert!(cache.contains("/b/1.jpg"))
+    }
+}
@@ -0,0 +1,98 @@
+use crate::client::{BucketResponse, ObjectClient};
+use crate::domain::error::ErrorResponse;
+use crate::domain::error::ErrorResponse::{ImageNotFoundError, SourceFetchError};
+use crate::domain::image_item::{ImageItem, OriginMetadata};
+use crate::domain::purge::{PurgeReport, PurgeScope};
+use crate::domain::status::BreakerStatus;
+use crate::repository::negative_cache::NegativeCache;
+use crate::repository::ImageRepository;
+use async_trait::async_trait;
+use hyper::body::Bytes;
+use tracing::{debug, error, info, instrument};
+
+/// Origin in object storage, a GCS bucket or S3-compatible storage depending on the
+/// client. Confirmed 404s are remembered in the negative cache.
+#[derive(Debug)]
+pub struct ObjectRepository<C: ObjectClient> {
+    client: C,
+    negative_cache: NegativeCache,
+}
+
+impl<C: ObjectClient> ObjectRepository<C> {
+    pub fn new(client: C, negative_cache: NegativeCache) -> Self {
+        ObjectRepository { client, negative_cache }
+    }
+}
+
+#[async_trait]
+impl<C: ObjectClient> ImageRepository for ObjectRepository<C> {
+    fn name(&self) -> &'static str {
+        self.client.name()
+    }
+
+    /// Request the image from the origin and bundle into an `ImageItem`.
+    #[instrument(skip(self))]
+    async fn read_image(&self, path: &str) -> Result<ImageItem, ErrorResponse> {
+        if self.negative_cache.contains(path) {
+            debug!("Known missing image at {path}");
+            return Err(ImageNotFoundError {});
+        }
+        match self.client.get(path).await {
+            Ok(BucketResponse::Fetched(bytes, metadata)) => Ok(ImageItem { bytes, metadata }),
+            Ok(BucketResponse::NotFound) => {
+                info!("{} has no image at {path}", self.client.name());
+                self.negative_cache.insert(path);
+                Err(ImageNotFoundError {})
+            }
+            Ok(BucketResponse::NotModified) => {
+                error!("Unconditional request for {path} answered with 304");
+                Err(SourceFetchError {})
+            }
+            Err(e) => {
+                error!("Could not read image at {path} from {}: {e}", self.client.name());
+                Err(e.into())
+            }
+        }
+    }
+
+    /// Conditionally re-request an image, `None` means the held copy is still current.
+    #[instrument(skip(self, metadata))]
+    async fn revalidate_image(
+        &self,
+        path: &str,
+        metadata: &OriginMetadata,
+    ) -> Result<Option<ImageItem>, ErrorResponse> {
+        match self.client.revalidate(path, metadata).await {
+            Ok(BucketResponse::Fetched(bytes, metadata)) => Ok(Some(ImageItem { bytes, metadata })),
+            Ok(BucketResponse::NotModified) => Ok(None),
+            Ok(BucketResponse::NotFound) => {
+                info!("Image at {path} was deleted");
+                self.negative_cache.insert(path);
+                Err(ImageNotFoundError {})
+            }
+            Err(e) => {
+                error!("Could not revalidate image at {path} in {}: {e}", self.client.name());
+                Err(e.into())
+            }
+        }
+    }
+
+    #[instrument(skip(self, bytes))]
+    async fn store_image(&self, path: &str, bytes: &Bytes, content_type: &str) -> Result<OriginMetadata, ErrorResponse> {
+        let metadata = self.client.put(path, bytes.clone(), content_type).await.map_err(|e| {
+            error!("Could not store image at {path} in {}: {e}", self.client.name());
+            ErrorResponse::from(e)
+        })?;
+        self.negative_cache.purge(&PurgeScope::Path(path.to_string()));
+        Ok(metadata)
+    }
+
+    fn breaker_status(&self) -> Option<BreakerStatus> {
+        Some(self.client.status())
+    }
+
+    /// Forget remembered 404s within `scope`.
+    async fn purge(&self, scope: &PurgeScope) -> Result<PurgeReport, ErrorResponse> {
+        Ok(self.negative_cache.purge(scope))
+    }
+}
@@ -1,49 +1,444 @@
+use crate::domain::image_item::{ImageItem, OriginMetadata};
+use crate::domain::purge::{PurgeReport, PurgeScope};
 use crate::repository::ImageRepository;
+use async_trait::async_trait;
 use crate::service::{ErrorResponse, ImageNotFoundInCacheError, ImageWriteError};
 use futures_util::TryFutureExt;
-use std::path::Path;
-use tracing::{error, info, instrument};
+use hyper::body::Bytes;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use tokio::io::AsyncWriteExt;
+use tracing::{error, info, instrument, warn};
 
+/// Cache of originals on the shared volume. Entries are stored under the SHA-256 of
+/// their request path, sharded as `ab/cd/<hash>`, with a sidecar holding the path itself.
+/// Each entry starts with the checksum of the image it holds, so both are replaced by a
+/// single rename and a reader can never pair an image with the checksum of another.
 #[derive(Debug)]
-pub struct VolumeRepository {}
+pub struct VolumeRepository {
+    root: PathBuf,
+}
+
+/// Directory under the root holding the caches of mounts, one directory per namespace.
+pub const MOUNTS_DIR: &str = "mounts";
+/// Directory under the root large originals are spooled to by default, never cached entries.
+pub const SPOOL_DIR: &str = "spool";
+const METADATA_SUFFIX: &str = ".meta.json";
+const TEMP_SUFFIX: &str = ".tmp";
+/// Start of every entry, followed by the big endian CRC32 of the image after it.
+const ENTRY_MAGIC: &[u8; 4] = b"IMC1";
+const ENTRY_HEADER_LEN: usize = ENTRY_MAGIC.len() + 4;
 
-const ROOT_PATH: &str = "/mnt/shared-cache";
+/// Contents of the sidecar stored next to each cached image.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Sidecar {
+    /// Request path the entry was cached under.
+    #[serde(default)]
+    key: String,
+    #[serde(flatten)]
+    metadata: OriginMetadata,
+    /// CRC32 of the cached image for entries written before the entry itself held it,
+    /// absent for entries older still.
+    #[serde(default, skip_serializing)]
+    checksum: Option<String>,
+}
 
 impl VolumeRepository {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        VolumeRepository { root: root.into() }
+    }
+
+    /// Move entries cached under the old layout, which mirrored the request path onto
+    /// the volume, into the hashed layout. Safe to run on several instances at once.
+    #[instrument]
+    pub async fn migrate_legacy_layout(&self) {
+        let mut migrated: u64 = 0;
+        let mut legacy_dirs: Vec<PathBuf> = Vec::new();
+        for file_path in self.files().await {
+            let Some(relative) = file_path.strip_prefix(&self.root).ok().and_then(Path::to_str) else { continue };
+            if is_hashed_entry(relative)
+                || relative.starts_with(&format!("{MOUNTS_DIR}/"))
+                || relative.starts_with(&format!("{SPOOL_DIR}/"))
+                || relative.ends_with(METADATA_SUFFIX)
+                || relative.ends_with(TEMP_SUFFIX)
+            {
+                continue;
+            }
+            let key = format!("/{relative}");
+            let legacy_sidecar = PathBuf::from(format!("{}{METADATA_SUFFIX}", file_path.display()));
+            let Ok(bytes) = tokio::fs::read(&file_path).await else { continue };
+            let item = ImageItem {
+                bytes: Bytes::from(bytes),
+                metadata: read_sidecar_file(&legacy_sidecar).await.metadata,
+            };
+            if self.write_image(&key, &item).await.is_err() {
+                warn!("Could not migrate cached image at {key}");
+                continue;
+            }
+            let _ = remove_file(&file_path).await;
+            let _ = remove_file(&legacy_sidecar).await;
+            if let Some(parent) = file_path.parent() {
+                legacy_dirs.push(parent.to_path_buf());
+            }
+            migrated += 1;
+        }
+        // Deepest first, directories still holding anything are left alone.
+        legacy_dirs.sort_by_key(|dir| std::cmp::Reverse(dir.components().count()));
+        legacy_dirs.dedup();
+        for dir in legacy_dirs {
+            let _ = tokio::fs::remove_dir(&dir).await;
+        }
+        info!("Migrated {migrated} cached images to the hashed layout");
+    }
+
+    fn entry_path(&self, path: &str) -> PathBuf {
+        let hash = hex::encode(Sha256::digest(path.as_bytes()));
+        self.root.join(&hash[0..2]).join(&hash[2..4]).join(hash)
+    }
+
+    /// Every cached image in the hashed layout.
+    async fn entry_files(&self) -> Vec<PathBuf> {
+        self.files()
+            .await
+            .into_iter()
+            .filter(|file_path| {
+                file_path
+                    .strip_prefix(&self.root)
+                    .ok()
+                    .and_then(Path::to_str)
+                    .is_some_and(is_hashed_entry)
+            })
+            .collect()
+    }
+
+    /// Every file under the root, whatever the layout.
+    async fn files(&self) -> Vec<PathBuf> {
+        let mut files = Vec::new();
+        let mut pending = vec![self.root.clone()];
+        while let Some(dir) = pending.pop() {
+            let Ok(mut entries) = tokio::fs::read_dir(&dir).await else { continue };
+            while let Ok(Some(entry)) = entries.next_entry().await {
+                match entry.file_type().await {
+                    Ok(file_type) if file_type.is_dir() => pending.push(entry.path()),
+                    Ok(_) => files.push(entry.path()),
+                    Err(_) => {}
+                }
+            }
+        }
+        files
+    }
+}
+
+/// Whether a path relative to the root is an image in the hashed layout, `ab/cd/abcd…`.
+fn is_hashed_entry(relative: &str) -> bool {
+    let parts: Vec<&str> = relative.split('/').collect();
+    match parts.as_slice() {
+        [first, second, hash] => {
+            hash.len() == 64
+                && hash.bytes().all(|b| b.is_ascii_hexdigit())
+                && hash.starts_with(&format!("{first}{second}"))
+        }
+        _ => false,
+    }
+}
+
+fn checksum(bytes: &[u8]) -> String {
+    format!("{:08x}", crc32fast::hash(bytes))
+}
+
+/// The image with its checksum in front, as stored on the volume.
+fn encode_entry(bytes: &[u8]) -> Vec<u8> {
+    let mut entry = Vec::with_capacity(ENTRY_HEADER_LEN + bytes.len());
+    entry.extend_from_slice(ENTRY_MAGIC);
+    entry.extend_from_slice(&crc32fast::hash(bytes).to_be_bytes());
+    entry.extend_from_slice(bytes);
+    entry
+}
+
+/// The image of an entry if it matches its checksum. Entries written before the checksum
+/// moved into them are checked against `legacy_checksum` from their sidecar, if any.
+fn decode_entry(entry: Vec<u8>, legacy_checksum: Option<&str>) -> Option<Bytes> {
+    let entry = Bytes::from(entry);
+    if entry.len() >= ENTRY_HEADER_LEN && entry.starts_with(ENTRY_MAGIC) {
+        let expected = u32::from_be_bytes(entry[ENTRY_MAGIC.len()..ENTRY_HEADER_LEN].try_into().ok()?);
+        let bytes = entry.slice(ENTRY_HEADER_LEN..);
+        return (crc32fast::hash(&bytes) == expected).then_some(bytes);
+    }
+    match legacy_checksum {
+        Some(expected) if expected != checksum(&entry) => None,
+        _ => Some(entry),
+    }
+}
+
+fn sidecar_path(entry_path: &Path) -> PathBuf {
+    PathBuf::from(format!("{}{METADATA_SUFFIX}", entry_path.display()))
+}
+
+/// Write to a uniquely named temp file next to `target`, sync it and rename it over the target.
+async fn write_atomic(target: &Path, contents: &[u8]) -> Result<(), ErrorResponse> {
+    let temp_path = PathBuf::from(format!("{}.{:016x}{TEMP_SUFFIX}", target.display(), rand::random::<u64>()));
+    let written = async {
+        let mut file = tokio::fs::File::create(&temp_path).await?;
+        file.write_all(contents).await?;
+        file.sync_all().await?;
+        tokio::fs::rename(&temp_path, target).await
+    }
+    .await;
+    if written.is_err() {
+        error!("Could not write {}", target.display());
+        let _ = tokio::fs::remove_file(&temp_path).await;
+        return Err(ImageWriteError {});
+    }
+    Ok(())
+}
+
+async fn write_sidecar(entry_path: &Path, sidecar: &Sidecar) -> Result<(), ErrorResponse> {
+    let contents = serde_json::to_vec(sidecar).map_err(|_| ImageWriteError {})?;
+    write_atomic(&sidecar_path(entry_path), &contents).await
+}
+
+/// Read the sidecar of a cached image, entries cached before sidecars existed
+/// get empty metadata which is always stale.
+async fn read_sidecar(entry_path: &Path) -> Sidecar {
+    read_sidecar_file(&sidecar_path(entry_path)).await
+}
+
+async fn read_sidecar_file(sidecar_path: &Path) -> Sidecar {
+    match tokio::fs::read(sidecar_path).await {
+        Ok(contents) => serde_json::from_slice(&contents).unwrap_or_else(|_| {
+            warn!("Discarding unreadable metadata at {}", sidecar_path.display());
+            Sidecar::default()
+        }),
+        Err(_) => Sidecar::default(),
+    }
+}
+
+/// Remove a file returning its size, a file that is already gone counts as zero bytes.
+async fn remove_file(path: &Path) -> Result<u64, ErrorResponse> {
+    let size = match tokio::fs::metadata(path).await {
+        Ok(metadata) => metadata.len(),
+        Err(_) => return Ok(0),
+    };
+    match tokio::fs::remove_file(path).await {
+        Ok(()) => Ok(size),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(0),
+        Err(_) => {
+            error!("Could not remove {}", path.display());
+            Err(ImageWriteError {})
+        }
+    }
+}
+
+#[async_trait]
+impl ImageRepository for VolumeRepository {
+    fn name(&self) -> &'static str {
+        "volume"
+    }
+
+    fn is_cache(&self) -> bool {
+        true
+    }
+
+    /// Write an image and its sidecar, each through a temp file renamed into place so
+    /// readers never see a partial file. The sidecar only describes the image, a reader
+    /// between the two renames sees the new image with the old metadata, which at worst
+    /// makes it revalidate early.
     #[instrument(skip(cache_item))]
-    pub async fn write_image(
+    async fn write_image(
         &self,
         path: &str,
-        cache_item: &[u8],
+        cache_item: &ImageItem,
     ) -> Result<(), ErrorResponse> {
-        let full_path = ROOT_PATH.to_string() + path;
-        let parent = Path::new(&full_path).parent().unwrap();
+        let entry_path = self.entry_path(path);
+        let parent = entry_path.parent().unwrap();
 
-        let _ = tokio::fs::create_dir_all(parent).await.map_err(|_| {
-            error!("Could not create dirs to image at {full_path}");
+        tokio::fs::create_dir_all(parent).await.map_err(|_| {
+            error!("Could not create dirs to image at {}", entry_path.display());
             ImageWriteError {}
         })?;
-        tokio::fs::write(&full_path, cache_item)
-            .await
-            .map_err(|_| {
-                error!("Could not write image at {full_path}");
-                ImageWriteError {}
-            })?;
-        Ok(())
+        write_atomic(&entry_path, &encode_entry(&cache_item.bytes)).await?;
+        let sidecar = Sidecar {
+            key: path.to_string(),
+            metadata: cache_item.metadata.clone(),
+            checksum: None,
+        };
+        write_sidecar(&entry_path, &sidecar).await
     }
-}
 
-impl ImageRepository for VolumeRepository {
+    /// Replace the origin metadata of a cached image.
+    #[instrument(skip(metadata))]
+    async fn write_metadata(
+        &self,
+        path: &str,
+        metadata: &OriginMetadata,
+    ) -> Result<(), ErrorResponse> {
+        let entry_path = self.entry_path(path);
+        let sidecar = Sidecar {
+            key: path.to_string(),
+            metadata: metadata.clone(),
+            checksum: None,
+        };
+        write_sidecar(&entry_path, &sidecar).await
+    }
+
+    /// Remove every cached image whose request path falls in `scope`, along with its sidecar.
     #[instrument]
-    async fn read_image(&self, path: &str) -> Result<Vec<u8>, ErrorResponse> {
-        let full_path = ROOT_PATH.to_string() + path;
+    async fn purge(&self, scope: &PurgeScope) -> Result<PurgeReport, ErrorResponse> {
+        let mut report = PurgeReport::default();
+        let entries = match scope {
+            PurgeScope::Path(path) => vec![self.entry_path(path)],
+            _ => self.entry_files().await,
+        };
+        for entry_path in entries {
+            if !scope.matches(&read_sidecar(&entry_path).await.key) {
+                continue;
+            }
+            let bytes = remove_file(&entry_path).await?;
+            if bytes == 0 && matches!(scope, PurgeScope::Path(_)) {
+                continue;
+            }
+            report.entries += 1;
+            report.bytes += bytes + remove_file(&sidecar_path(&entry_path)).await?;
+        }
+        info!("Purged {} entries, {} bytes from volume", report.entries, report.bytes);
+        Ok(report)
+    }
+
+    /// Read a cached image, an entry failing its checksum is removed and reported as a
+    /// cache miss so it is fetched again from the origin.
+    #[instrument]
+    async fn read_image(&self, path: &str) -> Result<ImageItem, ErrorResponse> {
+        let entry_path = self.entry_path(path);
 
-        let bytes: Vec<u8> = tokio::fs::read(&full_path)
+        let entry = tokio::fs::read(&entry_path)
             .map_err(|_| {
-                info!("FS could not read image at {full_path}");
+                info!("FS could not read image at {path}");
                 ImageNotFoundInCacheError {}
             })
             .await?;
-        Ok(bytes)
+        let sidecar = read_sidecar(&entry_path).await;
+        let Some(bytes) = decode_entry(entry, sidecar.checksum.as_deref()) else {
+            warn!("Checksum mismatch, evicting corrupted image at {path}");
+            let _ = remove_file(&entry_path).await;
+            let _ = remove_file(&sidecar_path(&entry_path)).await;
+            return Err(ImageNotFoundInCacheError {});
+        };
+        Ok(ImageItem {
+            bytes,
+            metadata: sidecar.metadata,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(bytes: &'static [u8]) -> ImageItem {
+        ImageItem {
+            bytes: Bytes::from_static(bytes),
+            metadata: OriginMetadata::default(),
+        }
+    }
+
+    #[test]
+    fn sidecar_reads_entries_without_checksum() {
+        let sidecar: Sidecar = serde_json::from_str(
+            r#"{"etag":"\"abc\"","last_modified":null,"generation":"1","fetched_at":5}"#,
+        )
+        .unwrap();
+        assert_eq!(sidecar.metadata.etag.as_deref(), Some("\"abc\""));
+        assert_eq!(sidecar.checksum, None)
+    }
+
+    #[test]
+    fn entry_path_is_sharded_hash() {
+        let repository = VolumeRepository::new("/cache");
+        let entry_path = repository.entry_path("/a/../../etc/passwd");
+        let relative = entry_path.strip_prefix("/cache").unwrap().to_str().unwrap();
+        assert!(is_hashed_entry(relative));
+        assert!(!is_hashed_entry("portfolio/cover.jpg"))
+    }
+
+    #[tokio::test]
+    async fn corrupted_entry_is_evicted() {
+        let root = tempfile::tempdir().unwrap();
+        let repository = VolumeRepository::new(root.path());
+        repository.write_image("/a.jpg", &item(b"image")).await.unwrap();
+        assert_eq!(repository.read_image("/a.jpg").await.unwrap().bytes, "image");
+
+        let entry_path = repository.entry_path("/a.jpg");
+        let mut entry = tokio::fs::read(&entry_path).await.unwrap();
+        entry.truncate(entry.len() - 2);
+        tokio::fs::write(&entry_path, entry).await.unwrap();
+        assert!(repository.read_image("/a.jpg").await.is_err());
+        assert!(!entry_path.exists());
+        assert!(!sidecar_path(&entry_path).exists())
+    }
+
+    #[tokio::test]
+    async fn entry_survives_a_sidecar_of_another_write() {
+        let root = tempfile::tempdir().unwrap();
+        let repository = VolumeRepository::new(root.path());
+        repository.write_image("/a.jpg", &item(b"old")).await.unwrap();
+        let old_sidecar = tokio::fs::read(sidecar_path(&repository.entry_path("/a.jpg"))).await.unwrap();
+        repository.write_image("/a.jpg", &item(b"new")).await.unwrap();
+        // As seen by a reader between the two renames of a concurrent write.
+        tokio::fs::write(sidecar_path(&repository.entry_path("/a.jpg")), old_sidecar).await.unwrap();
+        assert_eq!(repository.read_image("/a.jpg").await.unwrap().bytes, "new")
+    }
+
+    #[tokio::test]
+    async fn entries_checksummed_in_their_sidecar_are_verified() {
+        let root = tempfile::tempdir().unwrap();
+        let repository = VolumeRepository::new(root.path());
+        let entry_path = repository.entry_path("/a.jpg");
+        tokio::fs::create_dir_all(entry_path.parent().unwrap()).await.unwrap();
+        tokio::fs::write(&entry_path, b"image").await.unwrap();
+        let sidecar = format!(r#"{{"key":"/a.jpg","fetched_at":5,"checksum":"{}"}}"#, checksum(b"image"));
+        tokio::fs::write(sidecar_path(&entry_path), &sidecar).await.unwrap();
+        assert_eq!(repository.read_image("/a.jpg").await.unwrap().bytes, "image");
+
+        tokio::fs::write(&entry_path, b"imag").await.unwrap();
+        assert!(repository.read_image("/a.jpg").await.is_err())
+    }
+
+    #[tokio::test]
+    async fn purge_by_prefix_uses_recorded_keys() {
+        let root = tempfile::tempdir().unwrap();
+        let repository = VolumeRepository::new(root.path());
+        repository.write_image("/a/1.jpg", &item(b"one")).await.unwrap();
+        repository.write_image("/a/2.jpg", &item(b"two")).await.unwrap();
+        repository.write_image("/b/1.jpg", &item(b"three")).await.unwrap();
+
+        let report = repository.purge(&PurgeScope::Prefix("/a/".to_string())).await.unwrap();
+        assert_eq!(report.entries, 2);
+        assert!(repository.read_image("/a/1.jpg").await.is_err());
+        assert!(repository.read_image("/b/1.jpg").await.is_ok())
+    }
+
+    #[tokio::test]
+    async fn legacy_layout_is_migrated() {
+        let root = tempfile::tempdir().unwrap();
+        tokio::fs::create_dir_all(root.path().join("portfolio")).await.unwrap();
+        tokio::fs::write(root.path().join("portfolio/cover.jpg"), b"cover").await.unwrap();
+        tokio::fs::write(
+            root.path().join("portfolio/cover.jpg.meta.json"),
+            br#"{"etag":"\"abc\"","last_modified":null,"generation":null,"fetched_at":5}"#,
+        )
+        .await
+        .unwrap();
+        tokio::fs::create_dir_all(root.path().join(SPOOL_DIR)).await.unwrap();
+        tokio::fs::write(root.path().join(SPOOL_DIR).join("original-1"), b"spooled").await.unwrap();
+
+        let repository = VolumeRepository::new(root.path());
+        repository.migrate_legacy_layout().await;
+
+        let migrated = repository.read_image("/portfolio/cover.jpg").await.unwrap();
+        assert_eq!(migrated.bytes, "cover");
+        assert_eq!(migrated.metadata.etag.as_deref(), Some("\"abc\""));
+        assert!(!root.path().join("portfolio").exists());
+        assert!(root.path().join(SPOOL_DIR).join("original-1").exists())
     }
 }
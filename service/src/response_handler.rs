@@ -1,64 +1,134 @@
-use crate::domain::ImageData;
+use crate::domain::cache_policy::Outcome;
+use crate::domain::error::ErrorResponse;
+use crate::domain::{ImageData, ImageResponse};
+use crate::router::full;
 use crate::service::InternalResponse;
+use crate::CONFIG;
 use http_body_util::combinators::BoxBody;
 use hyper::body::Bytes;
+use hyper::header::InvalidHeaderValue;
 use hyper::http::HeaderValue;
-use hyper::Response;
+use hyper::{HeaderMap, Response, StatusCode};
 use opentelemetry::trace::SpanContext;
+use serde::Serialize;
 use std::error;
 use tracing::instrument;
 use tracing_opentelemetry::OpenTelemetrySpanExt;
 
 const IMAGE_HEADER_NAME: &str = "content-type";
 const CACHE_CONTROL_HEADER_NAME: &str = "cache-control";
-const CACHE_CONTROL_HEADER_VALUE: &str = "max-age=31536000";
 const IMAGE_HEADER_ROOT: &str = "image";
+const JSON_CONTENT_TYPE: &str = "application/json";
 const SERVER_TIMING_HEADER_NAME: &str = "server-timing";
 const TRACERESPONSE_HEADER: &str = "traceresponse";
 const CONTENT_LENGTH_HEADER_NAME: &str = "content-length";
+const LAST_MODIFIED_HEADER_NAME: &str = "last-modified";
+const LOCATION_HEADER_NAME: &str = "location";
 
 
 pub type ResultResponse =
 Result<Response<BoxBody<Bytes, hyper::Error>>, Box<dyn error::Error + Send + Sync>>;
 
 #[instrument(skip(response))]
-pub fn transform(response: InternalResponse) -> ResultResponse {
+pub fn transform(path: &str, response: InternalResponse) -> ResultResponse {
     match response {
-        Ok(ImageData {
+        Ok(ImageResponse::Image(ImageData {
                body,
                server_timing,
                format_extension,
                content_length,
-           }) => {
+               last_modified,
+           })) => {
             let mut response = Response::new(body);
             let header_map = response.headers_mut();
             {
                 header_map.insert(IMAGE_HEADER_NAME, HeaderValue::from_str(&(IMAGE_HEADER_ROOT.to_owned() + &*format_extension))?);
                 header_map.insert(SERVER_TIMING_HEADER_NAME, HeaderValue::from_str(&format!("{}", server_timing))?);
-                header_map.insert(CACHE_CONTROL_HEADER_NAME, HeaderValue::from_str(&CACHE_CONTROL_HEADER_VALUE)?);
+                insert_cache_policy(header_map, path, Outcome::Success)?;
                 header_map.insert(CONTENT_LENGTH_HEADER_NAME, HeaderValue::from_str(&content_length.to_string())?);
-                let context = tracing::Span::current().context().clone();
-                if let Some(span_context) = context.get::<SpanContext>() {
-                    let trace_id = span_context.trace_id();
-                    let span_id = span_context.span_id();
-                    let trace_flags = span_context.trace_flags();
-
-                    let traceresponse_value = format!(
-                        "00-{}-{}-{:?}",
-                        trace_id,
-                        span_id,
-                        trace_flags
-                    );
-
-                    header_map.insert(
-                        TRACERESPONSE_HEADER,
-                        HeaderValue::from_str(&traceresponse_value)?,
-                    );
+                if let Some(last_modified) = last_modified {
+                    header_map.insert(LAST_MODIFIED_HEADER_NAME, HeaderValue::from_str(&last_modified)?);
                 }
+                insert_traceresponse(header_map)?;
             }
 
             Ok(response)
         }
+        Ok(ImageResponse::NotModified { last_modified }) => {
+            let mut response = Response::new(full(Bytes::new()));
+            *response.status_mut() = StatusCode::NOT_MODIFIED;
+            let header_map = response.headers_mut();
+            insert_cache_policy(header_map, path, Outcome::Success)?;
+            header_map.insert(LAST_MODIFIED_HEADER_NAME, HeaderValue::from_str(&last_modified)?);
+            insert_traceresponse(header_map)?;
+            Ok(response)
+        }
+        Ok(ImageResponse::Redirect { location }) => {
+            let mut response = Response::new(full(Bytes::new()));
+            *response.status_mut() = StatusCode::FOUND;
+            let header_map = response.headers_mut();
+            insert_cache_policy(header_map, path, Outcome::Redirect)?;
+            header_map.insert(LOCATION_HEADER_NAME, HeaderValue::from_str(&location)?);
+            insert_traceresponse(header_map)?;
+            Ok(response)
+        }
+        Err(e) => {
+            let mut response = e.handle()?;
+            let outcome = match response.status() {
+                StatusCode::NOT_FOUND => Outcome::NotFound,
+                _ => Outcome::Error,
+            };
+            insert_cache_policy(response.headers_mut(), path, outcome)?;
+            Ok(response)
+        }
+    }
+}
+
+/// Set `Cache-Control`, and the CDN header when configured, from the policy for this path and outcome.
+/// `path` is that of the image, whatever form of URL it was requested with.
+fn insert_cache_policy(header_map: &mut HeaderMap, path: &str, outcome: Outcome) -> Result<(), InvalidHeaderValue> {
+    let cache_control = &CONFIG.cache_control;
+    let policy = cache_control.policy_for(path, outcome);
+    header_map.insert(CACHE_CONTROL_HEADER_NAME, HeaderValue::from_str(&policy.cache_control())?);
+    if let Some(surrogate_header) = cache_control.surrogate_header {
+        header_map.insert(surrogate_header.header_name(), HeaderValue::from_str(&policy.surrogate_control())?);
+    }
+    Ok(())
+}
+
+/// Serialize an admin/API result as a JSON response.
+#[instrument(skip(response))]
+pub fn transform_json<T: Serialize>(response: Result<T, ErrorResponse>) -> ResultResponse {
+    match response {
+        Ok(body) => {
+            let mut response = Response::new(full(serde_json::to_vec(&body)?));
+            let header_map = response.headers_mut();
+            header_map.insert(IMAGE_HEADER_NAME, HeaderValue::from_static(JSON_CONTENT_TYPE));
+            insert_traceresponse(header_map)?;
+            Ok(response)
+        }
         Err(e) => Ok(e.handle()?),
     }
 }
+
+fn insert_traceresponse(header_map: &mut HeaderMap) -> Result<(), InvalidHeaderValue> {
+    let context = tracing::Span::current().context().clone();
+    if let Some(span_context) = context.get::<SpanContext>() {
+        let trace_id = span_context.trace_id();
+        let span_id = span_context.span_id();
+        let trace_flags = span_context.trace_flags();
+
+        let traceresponse_value = format!(
+            "00-{}-{}-{:?}",
+            trace_id,
+            span_id,
+            trace_flags
+        );
+
+        header_map.insert(
+            TRACERESPONSE_HEADER,
+            HeaderValue::from_str(&traceresponse_value)?,
+        );
+    }
+    Ok(())
+}
@@ -1,15 +1,22 @@
 use std::error;
 
-use crate::response_handler::transform;
-use crate::service::process_resize;
+use crate::admin_service::{process_purge, process_upload};
+use crate::response_handler::{transform, transform_json};
+use crate::service::{process_image, process_presets, process_status};
 use http_body_util::{combinators::BoxBody, BodyExt, Full};
 use hyper::body::Bytes;
+use hyper::header::IF_MODIFIED_SINCE;
 use hyper::{Method, Request, Response, StatusCode};
 use opentelemetry::Context;
 use tracing::instrument;
 use tracing_opentelemetry::OpenTelemetrySpanExt;
 use crate::observability::propagators::HyperHeaderExtractor;
 
+/// Lists the configured presets, public as clients build `/p/<name>/<path>` URLs from it.
+const PRESETS_PATH: &str = "/presets";
+/// Uploads go to the original at the path following this prefix.
+const UPLOAD_PREFIX: &str = "/private/upload/";
+
 #[instrument]
 pub async fn router(
     req: Request<hyper::body::Incoming>,
@@ -20,14 +27,27 @@ pub async fn router(
     tracing::Span::current().set_parent(context);
     match (req.method(), req.uri().path(), req.uri().query()) {
         (&Method::GET, "/private/status", None) =>
-            Ok(Response::new(full("OK"))),
+            transform_json(process_status()),
+        (&Method::GET, PRESETS_PATH, None) =>
+            transform_json(process_presets()),
+        (&Method::POST, "/private/purge", query_params) =>
+            transform_json(process_purge(req.headers(), query_params).await),
+        (&Method::PUT | &Method::POST, path, _) if path.starts_with(UPLOAD_PREFIX) => {
+            let path = path[UPLOAD_PREFIX.len() - 1..].to_string();
+            let (parts, body) = req.into_parts();
+            transform_json(process_upload(&parts.headers, &path, body).await)
+        }
         (&Method::GET, "/", None) => {
             let no_content = Response::builder().status(StatusCode::NO_CONTENT).body(full(Bytes::new()))?;
             Ok(no_content)
         }
         (&Method::GET, path, query_params) => {
-            let resp = transform(process_resize(path, query_params).await);
-            resp
+            let if_modified_since = req
+                .headers()
+                .get(IF_MODIFIED_SINCE)
+                .and_then(|value| value.to_str().ok());
+            let (image_path, response) = process_image(path, query_params, if_modified_since).await;
+            transform(&image_path, response)
         }
         _ => {
             let mut not_found = Response::new(full("Endpoint not found"));
@@ -1,59 +1,336 @@
-pub(crate) use crate::domain::dimension::{decode, Dimension};
+pub(crate) use crate::domain::dimension::{canonical_query, decode, DecodedDimension, Dimension};
 pub(crate) use crate::domain::error::ErrorResponse;
 pub(crate) use crate::domain::error::ErrorResponse::*;
+use crate::domain::pipeline::{Operation, Pipeline, PIPELINE_PATH_PREFIX};
+use crate::domain::image_path::normalize_request;
+use crate::domain::imgproxy::{parse_imgproxy, split_imgproxy_path, ImgproxyConfig, ImgproxySigner};
+use crate::domain::preset::{expand_preset, split_preset_path, Preset, PRESET_PATH_PREFIX};
+use crate::domain::query::{unique_query_params, QueryParams};
 use crate::domain::server_timing::{timing::Timing, ServerTiming};
-use crate::domain::{ExtensionProvider, ImageData};
-use crate::image_service::{get_image, encode_image, resize_image, image_to_body};
-use image::DynamicImage;
+use crate::domain::source::ImageSource;
+use crate::domain::status::{CircuitState, ServiceStatus};
+use crate::domain::thumbor::{parse_thumbor, split_thumbor_path, verify_thumbor_signature, ThumborConfig};
+use crate::domain::transform::Transform;
+use crate::domain::url_signature::UrlSigner;
+use crate::repository::mount::Mount;
+use crate::{CONFIG, MOUNT_TABLE};
+use crate::domain::{format_from_path, ExtensionProvider, ImageData, ImageResponse};
+use crate::image_service::{
+    check_pipeline, decode_image, encode_image, get_source_image, image_to_body, run_pipeline,
+};
+use image::ImageFormat;
+use lazy_static::lazy_static;
+use std::collections::BTreeMap;
 use std::time::Instant;
 use tracing::instrument;
 
 use tracing::debug;
 
-pub type InternalResponse = Result<ImageData, ErrorResponse>;
+pub type InternalResponse = Result<ImageResponse, ErrorResponse>;
 
-#[instrument]
-pub async fn process_resize(path: &str, opt_query: Option<&str>) -> InternalResponse {
-    let process_timer: Instant = Instant::now();
+const URL_SIGNING_KEYS_ENV: &str = "URL_SIGNING_KEYS";
+const THUMBOR_SECURITY_KEY_ENV: &str = "THUMBOR_SECURITY_KEY";
+const IMGPROXY_KEY_ENV: &str = "IMGPROXY_KEY";
+const IMGPROXY_SALT_ENV: &str = "IMGPROXY_SALT";
 
-    let decoding_timer = Instant::now();
+lazy_static! {
+    /// Image requests need a signature while any key is configured.
+    static ref URL_SIGNER: UrlSigner = UrlSigner::from_env_var(URL_SIGNING_KEYS_ENV);
+    /// Security key of the Thumbor deployment whose URLs are served.
+    static ref THUMBOR_SECURITY_KEY: Option<Vec<u8>> =
+        std::env::var(THUMBOR_SECURITY_KEY_ENV).ok().filter(|key| !key.is_empty()).map(String::into_bytes);
+    /// Key and salt pairs of the imgproxy deployment whose URLs are served.
+    static ref IMGPROXY_SIGNER: ImgproxySigner = ImgproxySigner::from_env_vars(IMGPROXY_KEY_ENV, IMGPROXY_SALT_ENV);
+}
+
+/// Refuse unsigned or tampered image requests, before anything is fetched for them.
+pub fn verify_signature(path: &str, params: &QueryParams) -> Result<(), ErrorResponse> {
+    URL_SIGNER.verify(path, params, chrono::Utc::now().timestamp())
+}
+
+/// Refuse to start while `URL_SIGNING_KEYS` is set but Thumbor or imgproxy URLs are served
+/// without any signature, as they would get around it. Panics like `Config::load` does.
+pub fn check_url_signing() {
+    let unsigned = unsigned_url_forms(&CONFIG.thumbor, &CONFIG.imgproxy, IMGPROXY_SIGNER.is_enabled());
+    if URL_SIGNER.is_enabled() && !unsigned.is_empty() {
+        panic!("{URL_SIGNING_KEYS_ENV} is set but {} URLs are served unsigned", unsigned.join(" and "));
+    }
+}
+
+/// The URL forms served without a signature.
+fn unsigned_url_forms(thumbor: &ThumborConfig, imgproxy: &ImgproxyConfig, imgproxy_keys: bool) -> Vec<&'static str> {
+    let mut forms = Vec::new();
+    if thumbor.enabled && thumbor.allow_unsafe {
+        forms.push("Thumbor /unsafe/");
+    }
+    if imgproxy.enabled && imgproxy.allow_unsigned && !imgproxy_keys {
+        forms.push("imgproxy");
+    }
+    forms
+}
+
+/// Always answered, `DEGRADED` while the circuit of any origin is not closed.
+pub fn process_status() -> Result<ServiceStatus, ErrorResponse> {
+    let origins = MOUNT_TABLE.origin_statuses();
+    let degraded = origins
+        .iter()
+        .any(|origin| origin.breaker.state != CircuitState::Closed);
+    Ok(ServiceStatus {
+        status: if degraded { "DEGRADED" } else { "OK" },
+        origins,
+        cache_writes: MOUNT_TABLE.cache_write_statuses(),
+    })
+}
+
+/// Presets of the config, for clients to discover.
+pub fn process_presets() -> Result<&'static BTreeMap<String, Preset>, ErrorResponse> {
+    Ok(&CONFIG.presets)
+}
+
+/// An image request once its form is told apart and its signature checked.
+struct ImageRequest {
+    /// Normalized path of the image, as resolved against the mount table.
+    image_path: String,
+    /// The operations of a `/t/`, Thumbor or imgproxy URL, `None` for the query form.
+    pipeline: Option<Pipeline>,
+    preset: Option<String>,
+    params: QueryParams,
+}
+
+/// Serve an image request by the form of its path: a Thumbor URL, `/t/<operations>/<path>`,
+/// `/p/<preset>/<path>`, an imgproxy URL or a plain path, each within the mount the image path
+/// resolves to. Returns the image path next to the response, for the cache policy to be picked
+/// by, or the request path when it could not be told.
+pub async fn process_image(
+    path: &str,
+    opt_query: Option<&str>,
+    if_modified_since: Option<&str>,
+) -> (String, InternalResponse) {
+    let request = match image_request(path, opt_query) {
+        Ok(request) => request,
+        Err(e) => return (path.to_string(), Err(e)),
+    };
+    let (mount, mount_path) = MOUNT_TABLE.resolve(&request.image_path);
+    let response = match request.pipeline {
+        Some(pipeline) => process_pipeline(mount, &mount_path, pipeline, &request.params, if_modified_since).await,
+        None => {
+            let preset = request.preset.as_deref();
+            process_resize(mount, &mount_path, preset, &request.params, if_modified_since).await
+        }
+    };
+    (request.image_path, response)
+}
+
+/// Tell the form of a request apart. Thumbor and imgproxy URLs carry their own signature,
+/// every other form is checked by `verify_signature`.
+fn image_request(path: &str, opt_query: Option<&str>) -> Result<ImageRequest, ErrorResponse> {
+    if let Some((signature, rest)) = split_thumbor_path(path).filter(|_| CONFIG.thumbor.enabled) {
+        verify_thumbor_signature(&CONFIG.thumbor, THUMBOR_SECURITY_KEY.as_deref(), signature, rest)?;
+        let (pipeline, image_path) = parse_thumbor(rest)?;
+        return Ok(ImageRequest {
+            image_path: normalize_request(&image_path)?,
+            pipeline: Some(pipeline),
+            preset: None,
+            params: QueryParams::new(),
+        });
+    }
+    let own_form = path.starts_with(PIPELINE_PATH_PREFIX) || path.starts_with(PRESET_PATH_PREFIX);
+    if let Some((signature, rest)) = split_imgproxy_path(path).filter(|_| CONFIG.imgproxy.enabled && !own_form) {
+        IMGPROXY_SIGNER.verify(&CONFIG.imgproxy, signature, rest)?;
+        let (pipeline, image_path) = parse_imgproxy(rest)?;
+        return Ok(ImageRequest {
+            image_path: normalize_request(&image_path)?,
+            pipeline: Some(pipeline),
+            preset: None,
+            params: QueryParams::new(),
+        });
+    }
+    // Signed and resolved as normalized, `//partner/a.jpg` is served by the `/partner/` mount.
+    let path: &str = &normalize_request(path)?;
+    // Parsed once, so the signature covers exactly the parameters the transform reads.
+    let params = opt_query.map(unique_query_params).transpose()?.unwrap_or_default();
+    verify_signature(path, &params)?;
+    if path.starts_with(PIPELINE_PATH_PREFIX) {
+        // Operations and image path after `/t`, each segment still led by its slash.
+        let (pipeline, image_path) = Pipeline::parse_path(&path[PIPELINE_PATH_PREFIX.len() - 1..])?;
+        return Ok(ImageRequest {
+            image_path: image_path.to_string(),
+            pipeline: Some(pipeline),
+            preset: None,
+            params,
+        });
+    }
+    let (preset, image_path) = match split_preset_path(path) {
+        Some((preset, image_path)) => (Some(preset.to_string()), image_path),
+        None => (None, path),
+    };
+    Ok(ImageRequest {
+        image_path: image_path.to_string(),
+        pipeline: None,
+        preset,
+        params,
+    })
+}
+
+/// Transform the image at `path`, a path within `mount`, as the query and the preset named
+/// by the path or query say.
+#[instrument(skip(mount, params), fields(mount = mount.prefix))]
+pub async fn process_resize(
+    mount: &Mount,
+    path: &str,
+    preset: Option<&str>,
+    params: &QueryParams,
+    if_modified_since: Option<&str>,
+) -> InternalResponse {
     debug!("Processing query parameters");
-    let opt_dimension: Option<Dimension> = match opt_query {
-        Some(query) => decode(query).ok(),
-        None => None,
+    let expanded = &expand_preset(&CONFIG.presets, preset, params)?;
+    // A signed redirect would need the service to sign, the snapped size is served in place.
+    let redirect = CONFIG.dimensions.redirect && !URL_SIGNER.is_enabled();
+    let opt_dimension: Option<Dimension> = match decode(expanded, &CONFIG.limits, &CONFIG.dimensions) {
+        Ok(DecodedDimension { dimension, snapped: true }) if redirect => {
+            let location = snapped_location(&mount.prefix, preset, path, params, &dimension);
+            debug!("Redirecting to snapped {location}");
+            return Ok(ImageResponse::Redirect { location });
+        }
+        Ok(decoded) => Some(decoded.dimension),
+        Err(e @ DimensionNotAllowedError {}) => return Err(e),
+        _ => None,
     };
 
-    debug!("Dimensions parsed");
-    let (image, format) = get_image(path).await?;
-    let decoding_timing: Timing = Timing::new("dec", decoding_timer.elapsed(), None);
+    if opt_dimension.as_ref().is_some_and(|dimension| !mount.transform.allows(dimension)) {
+        return Err(DimensionNotAllowedError {});
+    }
+    let transform = Transform::from_params(expanded, opt_dimension)?;
+    debug!("Transform parsed");
+    render(mount, path, expanded, &Pipeline::from(transform), if_modified_since).await
+}
 
-    let resizing_timer = Instant::now();
+/// URL of the size a request was snapped to: the request as made, preset path included, with
+/// the width or height of `dimension`.
+fn snapped_location(
+    mount_prefix: &str,
+    preset: Option<&str>,
+    path: &str,
+    params: &QueryParams,
+    dimension: &Dimension,
+) -> String {
+    let preset_path = preset.map(|preset| format!("{PRESET_PATH_PREFIX}{preset}"));
+    format!(
+        "{}{}{path}?{}",
+        preset_path.unwrap_or_default(),
+        mount_prefix.trim_end_matches('/'),
+        canonical_query(params, dimension)
+    )
+}
 
-    let new_image: DynamicImage = match opt_dimension {
-        Some(dimension) => resize_image(dimension, image),
-        None => image,
-    };
+/// Run the operations of a `/t/` path on the image at `path`, a path within `mount`. Every
+/// resize is held to the limits, the dimension policy and the mount before anything is fetched.
+#[instrument(skip(mount, pipeline, params), fields(mount = mount.prefix))]
+pub async fn process_pipeline(
+    mount: &Mount,
+    path: &str,
+    mut pipeline: Pipeline,
+    params: &QueryParams,
+    if_modified_since: Option<&str>,
+) -> InternalResponse {
+    for operation in &mut pipeline.operations {
+        if let Operation::Resize { dimension, .. } = operation {
+            let (width, height) = match *dimension {
+                Dimension::Width(width) => (width, 0),
+                Dimension::Height(height) => (0, height),
+                Dimension::Bounds { width, height } => (width, height),
+            };
+            *dimension = Some(dimension.clone())
+                .filter(|_| CONFIG.limits.allows_output(width, height))
+                .and_then(|dimension| CONFIG.dimensions.apply(dimension))
+                .filter(|dimension| mount.transform.allows(dimension))
+                .ok_or(DimensionNotAllowedError {})?;
+        }
+    }
+    render(mount, path, params, &pipeline, if_modified_since).await
+}
+
+/// Fetch, decode, run `pipeline` on and encode the image at `path`, timing every step.
+async fn render(
+    mount: &Mount,
+    path: &str,
+    params: &QueryParams,
+    pipeline: &Pipeline,
+    if_modified_since: Option<&str>,
+) -> InternalResponse {
+    let process_timer: Instant = Instant::now();
 
-    let resizing_timing: Timing = Timing::new("res", resizing_timer.elapsed(), None);
+    let decoding_timer = Instant::now();
+    let source = ImageSource::from_request(path, params)?;
+    let item = get_source_image(&mount.chain, &source).await?;
+    let last_modified = item.metadata.last_modified.clone();
+    if let (Some(since), Some(last_modified)) = (if_modified_since, &last_modified) {
+        if item.metadata.not_modified_since(since) {
+            debug!("Not modified since {since}: {path}");
+            return Ok(ImageResponse::NotModified {
+                last_modified: last_modified.clone(),
+            });
+        }
+    }
+    let format = format_from_path(source.format_path());
+    let image = decode_image(item.bytes, format, &CONFIG.limits)?;
+    debug!("Image decoded at {path}");
+    let decoding_timing: Timing = Timing::new("dec", decoding_timer.elapsed(), None);
 
-    debug!("Image resized, writing image to buffer");
+    if let Err(e) = check_pipeline(pipeline, image.width(), image.height(), &CONFIG.limits) {
+        debug!("Pipeline does not apply to the {}x{} image: {path}", image.width(), image.height());
+        return Err(e);
+    }
+    let (new_image, operation_timings) = run_pipeline(image, pipeline);
+
+    debug!("Pipeline done, writing image to buffer");
 
     let encoding_timer = Instant::now();
-    let image_bytes = encode_image(new_image, format)?;
+    let output_format = pipeline.format().map(ImageFormat::from).unwrap_or(format);
+    let image_bytes = encode_image(new_image, output_format, pipeline.quality())?;
     let content_length: u64 = image_bytes.len() as u64;
     let body = image_to_body(image_bytes);
     let encoding_timing: Timing = Timing::new("enc", encoding_timer.elapsed(), None);
 
-
-    let format_extension: String = format.get_format_extension();
-    let server_timing: ServerTiming =
-        ServerTiming::new([decoding_timing, resizing_timing, encoding_timing].to_vec());
+    let format_extension: String = output_format.get_format_extension();
+    let timings = std::iter::once(decoding_timing)
+        .chain(operation_timings)
+        .chain(std::iter::once(encoding_timing))
+        .collect();
+    let server_timing: ServerTiming = ServerTiming::new(timings);
 
     debug!("Success {} ms: {path}", process_timer.elapsed().as_millis());
-    Ok(ImageData {
+    Ok(ImageResponse::Image(ImageData {
         body,
         server_timing,
         format_extension,
         content_length,
-    })
+        last_modified,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::query::query_params;
+
+    #[test]
+    fn snapped_requests_redirect_to_the_same_form() {
+        let params = query_params("width=500&fit=cover");
+        assert_eq!(snapped_location("/", None, "/a.jpg", &params, &Dimension::Width(640)), "/a.jpg?fit=cover&width=640");
+        assert_eq!(
+            snapped_location("/portfolio/", Some("thumb"), "/a.jpg", &params, &Dimension::Width(640)),
+            "/p/thumb/portfolio/a.jpg?fit=cover&width=640"
+        )
+    }
+
+    #[test]
+    fn unsigned_forms_are_found() {
+        let thumbor = ThumborConfig { enabled: true, allow_unsafe: true };
+        let imgproxy = ImgproxyConfig { enabled: true, allow_unsigned: true };
+        assert_eq!(unsigned_url_forms(&thumbor, &imgproxy, false), vec!["Thumbor /unsafe/", "imgproxy"]);
+        assert_eq!(unsigned_url_forms(&thumbor, &imgproxy, true), vec!["Thumbor /unsafe/"]);
+        let thumbor = ThumborConfig { enabled: false, ..thumbor };
+        assert!(unsigned_url_forms(&thumbor, &ImgproxyConfig::default(), false).is_empty())
+    }
 }